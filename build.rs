@@ -0,0 +1,38 @@
+use std::fs;
+
+/// Crates whose upgrades can change how TeX renders.
+const RENDERERS: &[&str] = &[
+    "rex",
+    "font",
+    "pathfinder_content",
+    "pathfinder_export",
+    "pathfinder_geometry",
+    "pathfinder_renderer",
+    "resvg",
+    "tiny-skia",
+    "usvg",
+    "webp",
+];
+
+/// Exposes the locked versions of the rendering crates as `RENDER_DEPS`, so
+/// validators for rendered images change when they do.
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    let lock = fs::read_to_string("Cargo.lock").unwrap_or_default();
+    let mut deps = Vec::new();
+    for package in lock.split("[[package]]") {
+        let field = |key: &str| {
+            package.lines().find_map(|line| {
+                let value = line.strip_prefix(key)?.trim().strip_prefix("= ")?;
+                Some(value.trim_matches('"').to_owned())
+            })
+        };
+        if let Some(name) = field("name").filter(|name| RENDERERS.contains(&name.as_str())) {
+            let version = field("version").unwrap_or_default();
+            // Git dependencies keep their version across commits
+            let source = field("source").unwrap_or_default();
+            deps.push(format!("{name}@{version}#{source}"));
+        }
+    }
+    println!("cargo:rustc-env=RENDER_DEPS={}", deps.join(" "));
+}
@@ -0,0 +1,456 @@
+use rex::parser::{
+    nodes::{AtomType, Symbol},
+    ParseNode,
+};
+
+use crate::{document::escape, tex::plain};
+
+/// Words for symbols a screen reader would otherwise spell out or skip.
+const SPOKEN_SYMBOLS: &[(char, &str)] = &[
+    ('+', "plus"),
+    ('-', "minus"),
+    ('−', "minus"),
+    ('±', "plus or minus"),
+    ('∓', "minus or plus"),
+    ('×', "times"),
+    ('⋅', "times"),
+    ('·', "times"),
+    ('∗', "times"),
+    ('÷', "divided by"),
+    ('/', "divided by"),
+    ('=', "equals"),
+    ('≠', "is not equal to"),
+    ('≈', "is approximately"),
+    ('<', "is less than"),
+    ('>', "is greater than"),
+    ('≤', "is less than or equal to"),
+    ('≥', "is greater than or equal to"),
+    ('∞', "infinity"),
+    ('∈', "is in"),
+    ('∉', "is not in"),
+    ('∪', "union"),
+    ('∩', "intersection"),
+    ('→', "approaches"),
+    ('⇒', "implies"),
+    ('∫', "the integral of"),
+    ('∑', "the sum of"),
+    ('∏', "the product of"),
+    ('∂', "partial"),
+    ('∇', "nabla"),
+    ('°', "degrees"),
+    ('%', "percent"),
+    ('!', "factorial"),
+    ('\'', "prime"),
+    ('′', "prime"),
+    ('α', "alpha"),
+    ('β', "beta"),
+    ('γ', "gamma"),
+    ('δ', "delta"),
+    ('ε', "epsilon"),
+    ('θ', "theta"),
+    ('λ', "lambda"),
+    ('μ', "mu"),
+    ('π', "pi"),
+    ('ρ', "rho"),
+    ('σ', "sigma"),
+    ('τ', "tau"),
+    ('φ', "phi"),
+    ('ω', "omega"),
+    ('Δ', "delta"),
+    ('Σ', "sigma"),
+    ('Ω', "omega"),
+];
+
+const ONES: &[&str] = &[
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+
+const TENS: &[&str] = &[
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+/// A run of nodes with multi-digit numbers and operator names, like `\sin`,
+/// joined into one item.
+enum Item<'a> {
+    Number(String),
+    Name(String),
+    Node(&'a ParseNode),
+}
+
+fn items(nodes: &[ParseNode]) -> Vec<Item<'_>> {
+    let mut items = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        if let Some(c) = symbol(node) {
+            let numeric = c.is_ascii_digit() || c == '.';
+            if let Some(Item::Number(number)) = items.last_mut() {
+                if numeric {
+                    number.push(c);
+                    continue;
+                }
+            }
+            if c.is_ascii_digit() {
+                items.push(Item::Number(c.to_string()));
+                continue;
+            }
+        }
+        if let ParseNode::AtomChange(change) = node {
+            if matches!(change.at, AtomType::Operator(_)) {
+                if let Some(name) = change.inner.iter().map(symbol).collect::<Option<String>>() {
+                    items.push(Item::Name(name));
+                    continue;
+                }
+            }
+        }
+        items.push(Item::Node(node));
+    }
+    items
+}
+
+fn symbol(node: &ParseNode) -> Option<char> {
+    match node {
+        ParseNode::Symbol(Symbol { codepoint, .. }) => Some(plain(*codepoint)),
+        _ => None,
+    }
+}
+
+/// MathML for the parse tree, wrapped in `<math>`.
+pub fn mathml(nodes: &[ParseNode]) -> String {
+    let mut out = String::from(r#"<math xmlns="http://www.w3.org/1998/Math/MathML">"#);
+    mathml_row(nodes, &mut out);
+    out.push_str("</math>");
+    out
+}
+
+fn mathml_row(nodes: &[ParseNode], out: &mut String) {
+    let items = items(nodes);
+    let wrap = items.len() != 1;
+    if wrap {
+        out.push_str("<mrow>");
+    }
+    for item in items {
+        match item {
+            Item::Number(number) => element("mn", &number, out),
+            Item::Name(name) => element("mi", &name, out),
+            Item::Node(node) => mathml_node(node, out),
+        }
+    }
+    if wrap {
+        out.push_str("</mrow>");
+    }
+}
+
+fn mathml_node(node: &ParseNode, out: &mut String) {
+    match node {
+        ParseNode::Symbol(symbol) => {
+            let c = plain(symbol.codepoint);
+            let tag = if c.is_alphabetic() { "mi" } else { "mo" };
+            element(tag, &c.to_string(), out);
+        }
+        ParseNode::Scripts(scripts) => {
+            let tag = match (&scripts.superscript, &scripts.subscript) {
+                (Some(_), Some(_)) => "msubsup",
+                (Some(_), None) => "msup",
+                (None, Some(_)) => "msub",
+                (None, None) => "mrow",
+            };
+            out.push_str(&format!("<{tag}>"));
+            match &scripts.base {
+                Some(base) => mathml_node(base, out),
+                None => out.push_str("<mrow/>"),
+            }
+            for script in [&scripts.subscript, &scripts.superscript]
+                .into_iter()
+                .flatten()
+            {
+                mathml_row(script, out);
+            }
+            out.push_str(&format!("</{tag}>"));
+        }
+        ParseNode::GenFraction(fraction) => {
+            out.push_str("<mfrac>");
+            mathml_row(&fraction.numerator, out);
+            mathml_row(&fraction.denominator, out);
+            out.push_str("</mfrac>");
+        }
+        ParseNode::Radical(radical) => {
+            out.push_str("<msqrt>");
+            mathml_row(&radical.inner, out);
+            out.push_str("</msqrt>");
+        }
+        ParseNode::Delimited(delimited) => {
+            out.push_str("<mrow>");
+            element("mo", &delimited.left.codepoint.to_string(), out);
+            mathml_row(&delimited.inner, out);
+            element("mo", &delimited.right.codepoint.to_string(), out);
+            out.push_str("</mrow>");
+        }
+        ParseNode::Accent(accent) => {
+            out.push_str("<mover accent=\"true\">");
+            mathml_row(&accent.nucleus, out);
+            element("mo", &accent.symbol.codepoint.to_string(), out);
+            out.push_str("</mover>");
+        }
+        ParseNode::Stack(stack) => {
+            out.push_str("<mtable>");
+            for line in &stack.lines {
+                out.push_str("<mtr><mtd>");
+                mathml_row(line, out);
+                out.push_str("</mtd></mtr>");
+            }
+            out.push_str("</mtable>");
+        }
+        ParseNode::Color(color) => mathml_row(&color.inner, out),
+        ParseNode::AtomChange(change) => mathml_row(&change.inner, out),
+        ParseNode::Group(inner) => mathml_row(inner, out),
+        // Spacing and style changes
+        _ => {}
+    }
+}
+
+fn element(tag: &str, text: &str, out: &mut String) {
+    out.push_str(&format!("<{tag}>{}</{tag}>", escape(text)));
+}
+
+/// The expression read out in English, like "x squared plus one".
+pub fn spoken(nodes: &[ParseNode]) -> String {
+    let mut words = Vec::new();
+    spoken_row(nodes, &mut words);
+    words.join(" ")
+}
+
+fn spoken_row(nodes: &[ParseNode], words: &mut Vec<String>) {
+    for item in items(nodes) {
+        match item {
+            Item::Number(number) => words.push(number_words(&number)),
+            Item::Name(name) => words.push(name),
+            Item::Node(node) => spoken_node(node, words),
+        }
+    }
+}
+
+fn spoken_node(node: &ParseNode, words: &mut Vec<String>) {
+    match node {
+        ParseNode::Symbol(symbol) => {
+            let c = plain(symbol.codepoint);
+            match SPOKEN_SYMBOLS.iter().find(|&&(s, _)| s == c) {
+                Some((_, word)) => words.push((*word).to_owned()),
+                None if c.is_alphanumeric() => words.push(c.to_string()),
+                // Brackets and punctuation are implied by the phrasing
+                None => {}
+            }
+        }
+        ParseNode::Scripts(scripts) => {
+            if let Some(base) = &scripts.base {
+                spoken_node(base, words);
+            }
+            if let Some(subscript) = &scripts.subscript {
+                words.push("sub".to_owned());
+                spoken_row(subscript, words);
+            }
+            if let Some(superscript) = &scripts.superscript {
+                match spoken(superscript).as_str() {
+                    "two" => words.push("squared".to_owned()),
+                    "three" => words.push("cubed".to_owned()),
+                    "prime" => words.push("prime".to_owned()),
+                    exponent if is_simple(superscript) => {
+                        words.push(format!("to the power of {exponent}"))
+                    }
+                    exponent => words.push(format!("to the power of {exponent}, end exponent")),
+                }
+            }
+        }
+        ParseNode::GenFraction(fraction) => {
+            let numerator = spoken(&fraction.numerator);
+            let denominator = spoken(&fraction.denominator);
+            if is_simple(&fraction.numerator) && is_simple(&fraction.denominator) {
+                words.push(format!("{numerator} over {denominator}"));
+            } else {
+                words.push(format!(
+                    "the fraction {numerator} over {denominator}, end fraction"
+                ));
+            }
+        }
+        ParseNode::Radical(radical) => {
+            let inner = spoken(&radical.inner);
+            if is_simple(&radical.inner) {
+                words.push(format!("the square root of {inner}"));
+            } else {
+                words.push(format!("the square root of {inner}, end root"));
+            }
+        }
+        ParseNode::Delimited(delimited) => {
+            let inner = spoken(&delimited.inner);
+            match (delimited.left.codepoint, delimited.right.codepoint) {
+                ('|', '|') => words.push(format!("the absolute value of {inner}")),
+                _ if is_simple(&delimited.inner) => words.push(inner),
+                _ => words.push(format!("open paren {inner} close paren")),
+            }
+        }
+        ParseNode::Accent(accent) => match accent.symbol.codepoint {
+            // Read before the nucleus, like "vector v"
+            '\u{20d7}' | '\u{20d1}' => {
+                words.push("vector".to_owned());
+                spoken_row(&accent.nucleus, words);
+            }
+            codepoint => {
+                spoken_row(&accent.nucleus, words);
+                words.push(accent_name(codepoint).to_owned());
+            }
+        },
+        ParseNode::Stack(stack) => {
+            let lines = stack
+                .lines
+                .iter()
+                .map(|line| spoken(line))
+                .collect::<Vec<_>>();
+            words.push(lines.join(", "));
+        }
+        ParseNode::Color(color) => spoken_row(&color.inner, words),
+        ParseNode::AtomChange(change) => spoken_row(&change.inner, words),
+        ParseNode::Group(inner) => spoken_row(inner, words),
+        _ => {}
+    }
+}
+
+/// What an accent is read as after its nucleus, from the combining mark or
+/// spacing character ReX sets it with.
+fn accent_name(codepoint: char) -> &'static str {
+    match codepoint {
+        '\u{304}' | '\u{305}' | '\u{af}' | '\u{203e}' => "bar",
+        '\u{307}' | '\u{2d9}' => "dot",
+        '\u{308}' | '\u{a8}' => "double dot",
+        '\u{303}' | '\u{2dc}' | '~' => "tilde",
+        '\u{302}' | '\u{2c6}' | '^' => "hat",
+        '\u{30c}' | '\u{2c7}' => "check",
+        '\u{306}' | '\u{2d8}' => "breve",
+        '\u{301}' | '\u{b4}' => "acute",
+        '\u{300}' | '`' => "grave",
+        '\u{30a}' | '\u{2da}' => "ring",
+        _ => "accent",
+    }
+}
+
+/// Whether `nodes` reads as a single word or number, so it needs no "end"
+/// marker to show where it stops.
+fn is_simple(nodes: &[ParseNode]) -> bool {
+    match items(nodes).as_slice() {
+        [Item::Number(_) | Item::Name(_)] => true,
+        [Item::Node(node)] => match node {
+            ParseNode::Symbol(_) => true,
+            ParseNode::Group(inner) => is_simple(inner),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Whole numbers under a million in words, anything else as written.
+fn number_words(number: &str) -> String {
+    match number.parse::<u32>() {
+        Ok(n) if n < 1_000_000 && !(number.len() > 1 && number.starts_with('0')) => {
+            below_million(n)
+        }
+        _ => number.to_owned(),
+    }
+}
+
+fn below_million(n: u32) -> String {
+    match n {
+        0..=19 => ONES[n as usize].to_owned(),
+        20..=99 => match n % 10 {
+            0 => TENS[(n / 10) as usize].to_owned(),
+            ones => format!("{}-{}", TENS[(n / 10) as usize], ONES[ones as usize]),
+        },
+        100..=999 => match n % 100 {
+            0 => format!("{} hundred", ONES[(n / 100) as usize]),
+            rest => format!(
+                "{} hundred {}",
+                ONES[(n / 100) as usize],
+                below_million(rest)
+            ),
+        },
+        _ => match n % 1000 {
+            0 => format!("{} thousand", below_million(n / 1000)),
+            rest => format!(
+                "{} thousand {}",
+                below_million(n / 1000),
+                below_million(rest)
+            ),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tex::parse_tree;
+
+    #[test]
+    fn speaks_expressions() {
+        let cases = [
+            ("x^2+1", "x squared plus one"),
+            ("25x", "twenty-five x"),
+            ("x_{n}", "x sub n"),
+            ("x^{n+1}", "x to the power of n plus one, end exponent"),
+            (r"\frac{1}{2}", "one over two"),
+            (
+                r"\frac{x+1}{2}",
+                "the fraction x plus one over two, end fraction",
+            ),
+            (r"\sqrt{x}", "the square root of x"),
+            (r"\sqrt{x+1}", "the square root of x plus one, end root"),
+            (r"\bar{x}", "x bar"),
+            (r"\dot{x}", "x dot"),
+            (r"\tilde{x}", "x tilde"),
+            (r"\hat{x}", "x hat"),
+            (r"\vec{v}", "vector v"),
+            ("a<b", "a is less than b"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(spoken(&parse_tree(input).unwrap()), expected, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn writes_mathml() {
+        let cases = [
+            (
+                "x^2+1",
+                "<mrow><msup><mi>x</mi><mn>2</mn></msup><mo>+</mo><mn>1</mn></mrow>",
+            ),
+            ("x_{n}", "<msub><mi>x</mi><mi>n</mi></msub>"),
+            (r"\frac{1}{2}", "<mfrac><mn>1</mn><mn>2</mn></mfrac>"),
+            (r"\sqrt{x}", "<msqrt><mi>x</mi></msqrt>"),
+            (
+                r"\bar{x}",
+                "<mover accent=\"true\"><mi>x</mi><mo>\u{304}</mo></mover>",
+            ),
+            ("a<b", "<mrow><mi>a</mi><mo>&lt;</mo><mi>b</mi></mrow>"),
+            ("a>b", "<mrow><mi>a</mi><mo>&gt;</mo><mi>b</mi></mrow>"),
+        ];
+        for (input, expected) in cases {
+            let expected =
+                format!(r#"<math xmlns="http://www.w3.org/1998/Math/MathML">{expected}</math>"#);
+            assert_eq!(mathml(&parse_tree(input).unwrap()), expected, "{input:?}");
+        }
+    }
+}
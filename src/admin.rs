@@ -0,0 +1,124 @@
+use axum::{
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    cache::{CacheStats, EntrySummary},
+    error::{Error, Result},
+    keys,
+    warm::{warm, WarmReport},
+    Data, Payload, State,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/cache", get(list).delete(purge_all))
+        .route("/cache/entry", post(entry))
+        .route("/cache/purge", post(purge))
+        .route("/cache/warm", post(warm_handler))
+        .route("/cache/stats", get(stats))
+        .route("/keys", get(keys::usage))
+        .route_layer(axum::middleware::from_fn(authorize))
+}
+
+/// Admin routes need `Authorization: Bearer $ADMIN_TOKEN`, and are disabled
+/// altogether when no token is configured.
+async fn authorize<B>(req: Request<B>, next: Next<B>) -> Response {
+    let expected = req
+        .extensions()
+        .get::<State>()
+        .and_then(|state| state.config.admin_token.clone());
+    let provided = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match (expected, provided) {
+        (Some(expected), Some(provided)) if constant_time_eq(&expected, provided) => {
+            next.run(req).await
+        }
+        _ => Error::Unauthorized.into_response(),
+    }
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+#[derive(Debug, Serialize)]
+struct Listing {
+    stats: CacheStats,
+    entries: Vec<EntrySummary>,
+}
+
+async fn list(Extension(state): Extension<State>) -> Json<Listing> {
+    let mut entries = state.response_cache.summaries().await;
+    entries.sort_by_key(|entry| entry.age_secs);
+    Json(Listing {
+        stats: state.response_cache.stats().await,
+        entries,
+    })
+}
+
+async fn stats(Extension(state): Extension<State>) -> Json<CacheStats> {
+    Json(state.response_cache.stats().await)
+}
+
+async fn entry(
+    Json(payload): Json<Payload>,
+    Extension(state): Extension<State>,
+) -> Result<Json<Data>> {
+    state
+        .response_cache
+        .get_raw(&payload.normalize()?)
+        .await
+        .map(Json)
+        .ok_or(Error::NotFound)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum Purge {
+    Key(Payload),
+    Prefix(String),
+}
+
+#[derive(Debug, Serialize)]
+struct Purged {
+    purged: usize,
+}
+
+async fn purge(
+    Json(purge): Json<Purge>,
+    Extension(state): Extension<State>,
+) -> Result<Json<Purged>> {
+    let purged = match purge {
+        Purge::Key(payload) => state.response_cache.purge(&payload.normalize()?).await,
+        Purge::Prefix(prefix) => state.response_cache.purge_prefix(&prefix).await,
+    };
+    info!("purged {purged} cache entries");
+    Ok(Json(Purged { purged }))
+}
+
+async fn purge_all(Extension(state): Extension<State>) -> Json<Purged> {
+    let purged = state.response_cache.purge_all().await;
+    info!("purged all {purged} cache entries");
+    Json(Purged { purged })
+}
+
+async fn warm_handler(
+    Json(payloads): Json<Vec<Payload>>,
+    Extension(state): Extension<State>,
+) -> Result<Json<WarmReport>> {
+    Ok(Json(warm(&state, payloads).await))
+}
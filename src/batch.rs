@@ -0,0 +1,102 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::ConnectInfo,
+    http::{header, HeaderMap},
+    response::IntoResponse,
+    Extension, Json,
+};
+use futures::{stream, StreamExt};
+use serde::Serialize;
+
+use crate::{
+    error::{Error, Result},
+    keys::Admitted,
+    solve_cached,
+    upstream::{client_key, Caller},
+    Data, Payload, State,
+};
+
+const MAX_BATCH_SIZE: usize = 500;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchItem {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Data>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<BatchError>,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchError {
+    status: u16,
+    message: String,
+}
+
+/// Solves every payload with bounded concurrency, writing one NDJSON line per
+/// item as soon as it's done. Lines arrive out of order, `index` refers to
+/// the position in the request. Each item counts against the API key like a
+/// request of its own.
+pub async fn handler(
+    Json(payloads): Json<Vec<Payload>>,
+    Extension(state): Extension<State>,
+    admitted: Option<Extension<Admitted>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    if payloads.len() > MAX_BATCH_SIZE {
+        return Err(Error::BadRequest(format!(
+            "batches are limited to {MAX_BATCH_SIZE} items"
+        )));
+    }
+    let concurrency = state.config.batch_concurrency;
+    let client = client_key(&headers, addr, &state.config.upstream);
+
+    let lines = stream::iter(payloads.into_iter().enumerate())
+        .map(move |(index, payload)| {
+            let state = state.clone();
+            let client = client.clone();
+            let admitted = admitted.clone();
+            async move {
+                let res = async {
+                    // `authenticate` already counted the request as the first
+                    if let (Some(Extension(admitted)), 1..) = (&admitted, index) {
+                        state.keys.charge(admitted).await?;
+                    }
+                    // Paced to the client's rate rather than rejected past its burst
+                    solve_cached(&state, Caller::Batch(&client), payload).await
+                }
+                .await;
+                let item = match res {
+                    Ok(data) => BatchItem {
+                        index,
+                        data: Some(data),
+                        error: None,
+                    },
+                    Err(e) => BatchItem {
+                        index,
+                        data: None,
+                        error: Some(BatchError {
+                            status: e.status().as_u16(),
+                            message: e.message(),
+                        }),
+                    },
+                };
+                let mut line = serde_json::to_vec(&item).unwrap_or_else(|e| {
+                    tracing::error!("failed to serialize batch item: {}", e);
+                    format!(r#"{{"index":{index},"error":{{"status":500,"message":"Something went wrong"}}}}"#).into_bytes()
+                });
+                line.push(b'\n');
+                Ok::<_, Infallible>(Bytes::from(line))
+            }
+        })
+        .buffer_unordered(concurrency);
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        StreamBody::new(lines),
+    ))
+}
@@ -0,0 +1,253 @@
+use std::{fs, path::PathBuf, sync::Arc, time::Instant};
+
+use anyhow::{anyhow, bail, Context};
+use clap::{Parser, Subcommand};
+use futures::{stream, StreamExt};
+use reqwest::Client;
+use serde_json::json;
+use symbolab_rs::{
+    clean_latex,
+    colour::Colour,
+    config::{Args, Config},
+    document::Document,
+    error::Error,
+    get_symbolab, get_webp, rasterise,
+    symbolab::Language,
+    tex,
+    token::{get_cached_token, get_token, token_factory, PoolStatus},
+    upstream::{Caller, Upstream},
+    Payload,
+};
+use tokio::sync::mpsc;
+use tracing::error;
+use tracing_subscriber::EnvFilter;
+
+/// Solves and typesets without running the server, using the same
+/// configuration it does.
+#[derive(Debug, Parser)]
+#[clap(name = "symbolab", version, about)]
+struct Cli {
+    /// TOML config file
+    #[clap(short, long, env = "CONFIG")]
+    config: Option<PathBuf>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Solves a problem and prints the steps
+    Solve {
+        query: String,
+        /// Print Symbolab's response as JSON instead
+        #[clap(long)]
+        json: bool,
+        #[clap(long, default_value = "en", value_parser = parse_language)]
+        lang: Language,
+    },
+    /// Typesets LaTeX to an .svg, .png or .webp file
+    Render {
+        latex: String,
+        #[clap(short, long)]
+        output: PathBuf,
+        #[clap(long)]
+        foreground: Option<Colour>,
+        #[clap(long)]
+        background: Option<Colour>,
+    },
+    /// Solves every line of a file, printing a JSON object per line
+    Batch {
+        /// One query per line, blank lines and lines starting with `#` are
+        /// skipped
+        file: PathBuf,
+        #[clap(long, default_value = "en", value_parser = parse_language)]
+        lang: Language,
+    },
+    /// Fetches a single token, to check Symbolab still hands them out
+    Token,
+    /// Solves a corpus into the cache snapshot the server loads at startup
+    Warm {
+        /// One query or JSON request body per line, blank lines and lines
+        /// starting with `#` are skipped
+        file: PathBuf,
+    },
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    // Warm-ups report progress as they go
+    let default_filter = match cli.command {
+        Command::Warm { .. } => "symbolab_rs=info",
+        _ => "warn",
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| default_filter.into()),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    let (foreground, background) = match &cli.command {
+        Command::Render {
+            foreground,
+            background,
+            ..
+        } => (*foreground, *background),
+        _ => (None, None),
+    };
+    let config = Config::load(&Args {
+        config: cli.config.clone(),
+        foreground,
+        background,
+        ..Default::default()
+    })?;
+    let client = Client::builder()
+        .user_agent(&config.tokens.user_agent)
+        .build()?;
+
+    match cli.command {
+        Command::Solve { query, json, lang } => {
+            let payload = payload(query, lang)?;
+            let token = get_token(&client).await?;
+            let symbolab = get_symbolab(&client, &token, &payload).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&symbolab)?);
+            } else {
+                print!("{}", Document::new(&symbolab).text());
+            }
+        }
+        Command::Render { latex, output, .. } => {
+            let render = &config.render;
+            let latex = clean_latex(&latex);
+            let extension = output.extension().and_then(|ext| ext.to_str());
+            let bytes = match extension {
+                Some("svg") => tex::get_svg(
+                    &latex,
+                    render.foreground,
+                    render.highlight,
+                    render.layout_width,
+                )?
+                .into_bytes(),
+                Some("png") => {
+                    let svg = tex::get_svg(
+                        &latex,
+                        render.foreground,
+                        render.highlight,
+                        render.layout_width,
+                    )?;
+                    rasterise(&svg, render.background, render)?.encode_png()?
+                }
+                Some("webp") => get_webp(
+                    &latex,
+                    render.foreground,
+                    render.highlight,
+                    render.background,
+                    render,
+                )?,
+                _ => bail!(
+                    "can't tell the format of {}, use .svg, .png or .webp",
+                    output.display()
+                ),
+            };
+            fs::write(&output, bytes)
+                .with_context(|| format!("failed to write {}", output.display()))?;
+        }
+        Command::Batch { file, lang } => {
+            let text = fs::read_to_string(&file)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            let queries = text
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_owned)
+                .collect::<Vec<_>>();
+
+            // Same token pool and upstream limits as the server
+            let (tx, mut rx) = mpsc::channel(config.tokens.buffer);
+            let token_config = config.tokens.clone();
+            tokio::spawn(async move {
+                let pool = Arc::new(PoolStatus::default());
+                if let Err(e) = token_factory(&mut rx, pool, &token_config).await {
+                    error!("token factory died: {:#}", e);
+                }
+            });
+            let upstream = Upstream::new(config.upstream.clone());
+            let wait_timeout = config.tokens.wait_timeout;
+
+            let mut results = stream::iter(queries)
+                .map(|query| {
+                    let (client, tx, upstream) = (&client, &tx, &upstream);
+                    async move {
+                        let res = async {
+                            let payload = payload(query.clone(), lang)?;
+                            upstream
+                                .call(Caller::Internal, || async {
+                                    let token = get_cached_token(tx, wait_timeout).await?;
+                                    Ok(get_symbolab(client, &token, &payload).await?)
+                                })
+                                .await
+                                .map_err(describe)
+                        }
+                        .await;
+                        (query, res)
+                    }
+                })
+                .buffered(config.batch_concurrency);
+            let mut failed = 0;
+            while let Some((query, res)) = results.next().await {
+                let line = match res {
+                    Ok(symbolab) => json!({ "query": query, "symbolab": symbolab }),
+                    Err(e) => {
+                        failed += 1;
+                        json!({ "query": query, "error": format!("{e:#}") })
+                    }
+                };
+                println!("{line}");
+            }
+            if failed > 0 {
+                bail!("{failed} queries failed");
+            }
+        }
+        Command::Token => {
+            let start = Instant::now();
+            let token = get_token(&client).await?;
+            eprintln!("fetched in {:?}", start.elapsed());
+            println!("{token}");
+        }
+        Command::Warm { file } => {
+            let report = symbolab_rs::warm::offline(config, &file).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !report.failed.is_empty() {
+                bail!("{} queries failed", report.failed.len());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn payload(query: String, language: Language) -> anyhow::Result<Payload> {
+    Payload {
+        query,
+        foreground: None,
+        background: None,
+        theme: None,
+        colours: Default::default(),
+        language,
+        options: Default::default(),
+        images: Default::default(),
+    }
+    .normalize()
+    .map_err(describe)
+}
+
+fn describe(e: Error) -> anyhow::Error {
+    match e {
+        Error::Internal(e) => e,
+        e => anyhow!(e.message()),
+    }
+}
+
+fn parse_language(language: &str) -> Result<Language, String> {
+    serde_json::from_value(json!(language)).map_err(|_| format!("unknown language `{language}`"))
+}
@@ -0,0 +1,266 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    env, fmt, fs, io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use anyhow::Context;
+
+use axum::{
+    body::Bytes,
+    extract::Path,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::error;
+
+use crate::{config::env_or, etag, State};
+
+/// How images are delivered in an `ImageSet`.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageMode {
+    /// Base64 data URIs embedded in the response.
+    #[default]
+    Inline,
+    /// URLs to `GET /img/{hash}.{ext}`.
+    Url,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlobConfig {
+    /// Prefix for image URLs, empty for relative ones.
+    pub public_url: String,
+    /// Bound on the in-memory store, unused with `dir`.
+    pub max_bytes: usize,
+    /// Keeps images as files here instead of in memory, so their URLs keep
+    /// working across restarts. Mount the same storage on every instance to
+    /// serve each other's URLs.
+    pub dir: Option<PathBuf>,
+}
+
+impl Default for BlobConfig {
+    fn default() -> Self {
+        Self {
+            public_url: String::new(),
+            max_bytes: 512 * 1024 * 1024,
+            dir: None,
+        }
+    }
+}
+
+impl BlobConfig {
+    pub fn apply_env(&mut self) -> anyhow::Result<()> {
+        self.public_url = env_or("PUBLIC_URL", self.public_url.clone())?;
+        self.max_bytes = env_or("BLOB_STORE_MAX_BYTES", self.max_bytes)?;
+        if let Ok(dir) = env::var("BLOB_DIR") {
+            self.dir = Some(dir).filter(|dir| !dir.is_empty()).map(PathBuf::from);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Blob {
+    content_type: &'static str,
+    bytes: Bytes,
+}
+
+/// Where blobs are kept, by file name (`{hash}.{ext}`).
+trait Backend: fmt::Debug + Send + Sync {
+    fn put(&self, name: &str, bytes: &[u8]) -> anyhow::Result<()>;
+    fn get(&self, name: &str) -> anyhow::Result<Option<Bytes>>;
+    /// Whether blobs outlive the process.
+    fn persistent(&self) -> bool;
+}
+
+#[derive(Debug, Default)]
+struct Blobs {
+    by_name: HashMap<String, Bytes>,
+    /// Insertion order, oldest blobs are evicted first.
+    order: VecDeque<String>,
+    size: usize,
+}
+
+/// Bounded, so keep `BLOB_STORE_MAX_BYTES` well above what the response
+/// cache references or old cached responses will point at evicted images.
+#[derive(Debug)]
+struct Memory {
+    max_bytes: usize,
+    blobs: RwLock<Blobs>,
+}
+
+impl Backend for Memory {
+    fn put(&self, name: &str, bytes: &[u8]) -> anyhow::Result<()> {
+        let mut blobs = self.blobs.write().unwrap();
+        if blobs.by_name.contains_key(name) {
+            return Ok(());
+        }
+        blobs.size += bytes.len();
+        blobs.order.push_back(name.to_owned());
+        blobs
+            .by_name
+            .insert(name.to_owned(), Bytes::copy_from_slice(bytes));
+        while blobs.size > self.max_bytes && blobs.order.len() > 1 {
+            let Some(oldest) = blobs.order.pop_front() else {
+                break;
+            };
+            if let Some(bytes) = blobs.by_name.remove(&oldest) {
+                blobs.size -= bytes.len();
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> anyhow::Result<Option<Bytes>> {
+        Ok(self.blobs.read().unwrap().by_name.get(name).cloned())
+    }
+
+    fn persistent(&self) -> bool {
+        false
+    }
+}
+
+/// Tells apart the temporary files of concurrent writes.
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// One file per blob. Nothing is deleted, prune old files externally if the
+/// directory grows too large.
+#[derive(Debug)]
+struct Disk {
+    dir: PathBuf,
+}
+
+impl Backend for Disk {
+    fn put(&self, name: &str, bytes: &[u8]) -> anyhow::Result<()> {
+        let path = self.dir.join(name);
+        if path.exists() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        // Renamed into place so readers never see a partial file, from a
+        // name no other writer of the same blob is using
+        let unique = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let tmp = self
+            .dir
+            .join(format!(".{name}.{}.{unique}", std::process::id()));
+        fs::write(&tmp, bytes).with_context(|| format!("failed to write {}", tmp.display()))?;
+        if let Err(e) = fs::rename(&tmp, &path) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e).with_context(|| format!("failed to write {}", path.display()));
+        }
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> anyhow::Result<Option<Bytes>> {
+        let path = self.dir.join(name);
+        match fs::read(&path) {
+            Ok(bytes) => Ok(Some(bytes.into())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    fn persistent(&self) -> bool {
+        true
+    }
+}
+
+/// Content-addressed store for rendered images, in memory or on disk
+/// depending on `BlobConfig::dir`.
+#[derive(Debug)]
+pub struct BlobStore {
+    public_url: String,
+    backend: Arc<dyn Backend>,
+}
+
+impl BlobStore {
+    pub fn new(config: &BlobConfig) -> Self {
+        let backend: Arc<dyn Backend> = match &config.dir {
+            Some(dir) => Arc::new(Disk { dir: dir.clone() }),
+            None => Arc::new(Memory {
+                max_bytes: config.max_bytes,
+                blobs: RwLock::new(Blobs::default()),
+            }),
+        };
+        Self {
+            public_url: config.public_url.trim_end_matches('/').to_owned(),
+            backend,
+        }
+    }
+
+    /// Stores `bytes` and returns the URL it's served from.
+    pub async fn put(&self, ext: &str, bytes: &[u8]) -> anyhow::Result<String> {
+        let hash = format!("{:x}", Sha256::digest(bytes));
+        let name = format!("{hash}.{ext}");
+        let url = format!("{}/img/{name}", self.public_url);
+        let backend = self.backend.clone();
+        let bytes = bytes.to_vec();
+        tokio::task::spawn_blocking(move || backend.put(&name, &bytes)).await??;
+        Ok(url)
+    }
+
+    /// Whether URLs from `put` keep working after a restart.
+    pub fn persistent(&self) -> bool {
+        self.backend.persistent()
+    }
+
+    fn get(&self, name: &str) -> anyhow::Result<Option<Blob>> {
+        let Some((hash, ext)) = name.split_once('.') else {
+            return Ok(None);
+        };
+        // Names come from the URL, so don't let them reach outside the store
+        let valid_hash = hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit());
+        if !valid_hash || !ext.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Ok(None);
+        }
+        Ok(self.backend.get(name)?.map(|bytes| Blob {
+            content_type: content_type(ext),
+            bytes,
+        }))
+    }
+}
+
+fn content_type(ext: &str) -> &'static str {
+    match ext {
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        _ => "application/octet-stream",
+    }
+}
+
+/// `GET /img/{hash}.{ext}`
+pub async fn handler(
+    Path(file): Path<String>,
+    Extension(state): Extension<State>,
+    headers: HeaderMap,
+) -> Response {
+    let blobs = state.blobs.clone();
+    let name = file.clone();
+    let res = tokio::task::spawn_blocking(move || blobs.get(&name))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|res| res);
+    let blob = match res {
+        Ok(Some(blob)) => blob,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("failed to read blob {file}: {:#}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let hash = file.split_once('.').map_or(file.as_str(), |(hash, _)| hash);
+    etag::respond(&headers, &format!("\"{hash}\""), etag::IMMUTABLE, || {
+        ([(header::CONTENT_TYPE, blob.content_type)], blob.bytes)
+    })
+}
@@ -0,0 +1,354 @@
+use std::{
+    collections::HashMap,
+    env, io,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::debug;
+
+use crate::{blob::ImageMode, config::env_duration, Data, Payload};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// How long an entry is served as-is.
+    #[serde(with = "humantime_serde")]
+    pub ttl: Duration,
+    /// How long past `ttl` an entry is still served while it's refreshed in
+    /// the background.
+    #[serde(with = "humantime_serde")]
+    pub stale_while_revalidate: Duration,
+    /// How long past `ttl` an entry may be served when upstream is failing.
+    #[serde(with = "humantime_serde")]
+    pub stale_if_error: Duration,
+    /// Written on shutdown and loaded on startup when set.
+    pub snapshot_path: Option<PathBuf>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(60 * 60),
+            stale_while_revalidate: Duration::from_secs(24 * 60 * 60),
+            stale_if_error: Duration::from_secs(7 * 24 * 60 * 60),
+            snapshot_path: None,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn apply_env(&mut self) -> anyhow::Result<()> {
+        self.ttl = env_duration("CACHE_TTL_SECS", self.ttl, Duration::from_secs)?;
+        self.stale_while_revalidate = env_duration(
+            "CACHE_STALE_WHILE_REVALIDATE_SECS",
+            self.stale_while_revalidate,
+            Duration::from_secs,
+        )?;
+        self.stale_if_error = env_duration(
+            "CACHE_STALE_IF_ERROR_SECS",
+            self.stale_if_error,
+            Duration::from_secs,
+        )?;
+        if let Ok(path) = env::var("CACHE_SNAPSHOT_PATH") {
+            self.snapshot_path = Some(path)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    data: Data,
+    fetched: Instant,
+    revalidating: bool,
+    /// Serialized size, as an estimate of how much memory the entry holds.
+    size: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntrySummary {
+    pub key: Payload,
+    pub size_bytes: usize,
+    pub age_secs: u64,
+    pub stale: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub entries: usize,
+    pub size_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotEntry {
+    key: Payload,
+    data: Data,
+    /// Wall-clock seconds since the epoch, so time spent between saving and
+    /// loading counts towards the age.
+    fetched_at: u64,
+}
+
+pub enum Lookup {
+    Fresh(Data),
+    /// Served immediately, `revalidate` is set for the one caller that
+    /// should refresh it.
+    Stale {
+        data: Data,
+        revalidate: bool,
+    },
+    /// Too old to serve unless upstream fails.
+    Expired(Data),
+    Miss,
+}
+
+#[derive(Debug)]
+pub struct ResponseCache {
+    config: CacheConfig,
+    /// Whether image URLs in cached responses survive a restart, so entries
+    /// using them can be snapshotted.
+    persistent_urls: bool,
+    entries: RwLock<HashMap<Payload, Entry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl ResponseCache {
+    pub fn new(config: CacheConfig, persistent_urls: bool) -> Self {
+        Self {
+            config,
+            persistent_urls,
+            entries: RwLock::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    pub async fn lookup(&self, payload: &Payload) -> Lookup {
+        let lookup = self.lookup_uncounted(payload).await;
+        let counter = match lookup {
+            Lookup::Fresh(_) | Lookup::Stale { .. } => &self.hits,
+            Lookup::Expired(_) | Lookup::Miss => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        lookup
+    }
+
+    async fn lookup_uncounted(&self, payload: &Payload) -> Lookup {
+        {
+            let reader = self.entries.read().await;
+            match reader.get(payload) {
+                None => return Lookup::Miss,
+                Some(entry) if entry.fetched.elapsed() < self.config.ttl => {
+                    return Lookup::Fresh(entry.data.clone());
+                }
+                Some(_) => {}
+            }
+        }
+
+        let mut writer = self.entries.write().await;
+        let Some(entry) = writer.get_mut(payload) else {
+            return Lookup::Miss;
+        };
+        let age = entry.fetched.elapsed();
+        let mut data = entry.data.clone();
+        data.stale = age >= self.config.ttl;
+        if !data.stale {
+            Lookup::Fresh(data)
+        } else if age < self.config.ttl + self.config.stale_while_revalidate {
+            let revalidate = !entry.revalidating;
+            entry.revalidating = true;
+            Lookup::Stale { data, revalidate }
+        } else if age < self.config.ttl + self.config.stale_if_error {
+            Lookup::Expired(data)
+        } else {
+            writer.remove(payload);
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+            Lookup::Miss
+        }
+    }
+
+    pub async fn insert(&self, payload: Payload, mut data: Data) {
+        data.cached = true;
+        data.stale = false;
+        let size = serde_json::to_vec(&data).map_or(0, |json| json.len());
+        let mut writer = self.entries.write().await;
+        writer.insert(
+            payload,
+            Entry {
+                data,
+                fetched: Instant::now(),
+                revalidating: false,
+                size,
+            },
+        );
+    }
+
+    /// Lets another caller retry a failed revalidation.
+    pub async fn revalidation_failed(&self, payload: &Payload) {
+        let mut writer = self.entries.write().await;
+        if let Some(entry) = writer.get_mut(payload) {
+            entry.revalidating = false;
+        }
+    }
+
+    /// Drops entries too old to be served even on error.
+    pub async fn sweep(&self) {
+        let max_age = self.config.ttl + self.config.stale_if_error;
+        let mut writer = self.entries.write().await;
+        let before = writer.len();
+        writer.retain(|_, entry| entry.fetched.elapsed() < max_age);
+        let swept = before - writer.len();
+        self.evictions.fetch_add(swept as u64, Ordering::Relaxed);
+        debug!("swept {} expired cache entries", swept);
+    }
+
+    pub async fn summaries(&self) -> Vec<EntrySummary> {
+        let reader = self.entries.read().await;
+        reader
+            .iter()
+            .map(|(key, entry)| EntrySummary {
+                key: key.clone(),
+                size_bytes: entry.size,
+                age_secs: entry.fetched.elapsed().as_secs(),
+                stale: entry.fetched.elapsed() >= self.config.ttl,
+            })
+            .collect()
+    }
+
+    /// The entry as stored, regardless of age and without counting a hit.
+    pub async fn get_raw(&self, payload: &Payload) -> Option<Data> {
+        let reader = self.entries.read().await;
+        reader.get(payload).map(|entry| entry.data.clone())
+    }
+
+    pub async fn purge(&self, payload: &Payload) -> usize {
+        let mut writer = self.entries.write().await;
+        writer.remove(payload).map_or(0, |_| 1)
+    }
+
+    /// Removes every entry whose query starts with `prefix`.
+    pub async fn purge_prefix(&self, prefix: &str) -> usize {
+        let mut writer = self.entries.write().await;
+        let before = writer.len();
+        writer.retain(|key, _| !key.query.starts_with(prefix));
+        before - writer.len()
+    }
+
+    pub async fn purge_all(&self) -> usize {
+        let mut writer = self.entries.write().await;
+        let purged = writer.len();
+        writer.clear();
+        purged
+    }
+
+    pub async fn stats(&self) -> CacheStats {
+        let reader = self.entries.read().await;
+        CacheStats {
+            entries: reader.len(),
+            size_bytes: reader.values().map(|entry| entry.size).sum(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Writes every entry that can be restored in another process to
+    /// `snapshot_path`, returning how many were written.
+    pub async fn save_snapshot(&self) -> anyhow::Result<usize> {
+        let Some(path) = &self.config.snapshot_path else {
+            return Ok(0);
+        };
+        let now = unix_secs(SystemTime::now());
+        let (json, count) = {
+            let reader = self.entries.read().await;
+            let entries = reader
+                .iter()
+                // Unless blobs are on disk, URLs would point at images that
+                // are gone after a restart
+                .filter(|(key, _)| self.persistent_urls || key.images == ImageMode::Inline)
+                .map(|(key, entry)| SnapshotEntry {
+                    key: key.clone(),
+                    data: entry.data.clone(),
+                    fetched_at: now.saturating_sub(entry.fetched.elapsed().as_secs()),
+                })
+                .collect::<Vec<_>>();
+            (serde_json::to_vec(&entries)?, entries.len())
+        };
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, json)
+            .await
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(count)
+    }
+
+    /// Loads entries written by `save_snapshot`, skipping those too old to be
+    /// served. A missing snapshot isn't an error.
+    pub async fn load_snapshot(&self) -> anyhow::Result<usize> {
+        let Some(path) = &self.config.snapshot_path else {
+            return Ok(0);
+        };
+        let json = match tokio::fs::read(path).await {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let entries: Vec<SnapshotEntry> = serde_json::from_slice(&json)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        let max_age = self.config.ttl + self.config.stale_if_error;
+        let now = Instant::now();
+        let wall_now = unix_secs(SystemTime::now());
+        let mut writer = self.entries.write().await;
+        let before = writer.len();
+        for SnapshotEntry {
+            key,
+            data,
+            fetched_at,
+        } in entries
+        {
+            let age = Duration::from_secs(wall_now.saturating_sub(fetched_at));
+            let Some(fetched) = now.checked_sub(age).filter(|_| age < max_age) else {
+                continue;
+            };
+            let size = serde_json::to_vec(&data).map_or(0, |json| json.len());
+            writer.insert(
+                key,
+                Entry {
+                    data,
+                    fetched,
+                    revalidating: false,
+                    size,
+                },
+            );
+        }
+        Ok(writer.len() - before)
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.config.ttl
+    }
+
+    pub fn sweep_interval(&self) -> Duration {
+        self.config.ttl
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,408 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// An sRGB colour with alpha, parsed from any CSS colour syntax we support:
+/// `#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`, `rgb()`, `rgba()`, `hsl()`,
+/// `hsla()` and the CSS named colours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Colour {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+#[derive(Debug)]
+pub struct ParseColourError(String);
+
+impl fmt::Display for ParseColourError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid colour `{}`", self.0)
+    }
+}
+
+impl std::error::Error for ParseColourError {}
+
+impl Colour {
+    pub const BLACK: Colour = Colour::rgba(0, 0, 0, 255);
+    pub const TRANSPARENT: Colour = Colour::rgba(0, 0, 0, 0);
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
+    /// `#rrggbbaa`, the canonical form used in cache keys and ETags.
+    pub fn to_hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}{:02x}", self.r, self.g, self.b, self.a)
+    }
+}
+
+impl fmt::Display for Colour {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl FromStr for Colour {
+    type Err = ParseColourError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let input = s.trim().to_ascii_lowercase();
+        let parsed = if let Some(hex) = input.strip_prefix('#') {
+            parse_hex(hex)
+        } else if let Some(args) = function(&input, &["rgb", "rgba"]) {
+            parse_rgb(args)
+        } else if let Some(args) = function(&input, &["hsl", "hsla"]) {
+            parse_hsl(args)
+        } else {
+            // Bare hex was accepted before the CSS syntaxes were
+            named(&input).or_else(|| parse_hex(&input))
+        };
+        parsed.ok_or_else(|| ParseColourError(s.to_owned()))
+    }
+}
+
+impl Serialize for Colour {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Colour {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Colour> {
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).ok().map(|d| d * 17);
+    let pair = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    match hex.len() {
+        3 => Some(Colour::rgba(digit(0)?, digit(1)?, digit(2)?, 255)),
+        4 => Some(Colour::rgba(digit(0)?, digit(1)?, digit(2)?, digit(3)?)),
+        6 => Some(Colour::rgba(pair(0)?, pair(2)?, pair(4)?, 255)),
+        8 => Some(Colour::rgba(pair(0)?, pair(2)?, pair(4)?, pair(6)?)),
+        _ => None,
+    }
+}
+
+/// The arguments of `name(...)` for any of `names`.
+fn function<'a>(input: &'a str, names: &[&str]) -> Option<&'a str> {
+    let (name, rest) = input.split_once('(')?;
+    if !names.contains(&name.trim()) {
+        return None;
+    }
+    rest.strip_suffix(')')
+}
+
+/// Splits both `a, b, c, d` and `a b c / d`.
+fn arguments(args: &str) -> Option<(Vec<&str>, Option<&str>)> {
+    let args = args.trim();
+    if args.contains(',') {
+        let mut parts = args.split(',').map(str::trim).collect::<Vec<_>>();
+        let alpha = if parts.len() == 4 { parts.pop() } else { None };
+        (parts.len() == 3).then_some((parts, alpha))
+    } else {
+        let (channels, alpha) = match args.split_once('/') {
+            Some((channels, alpha)) => (channels, Some(alpha.trim())),
+            None => (args, None),
+        };
+        let parts = channels.split_whitespace().collect::<Vec<_>>();
+        (parts.len() == 3).then_some((parts, alpha))
+    }
+}
+
+fn number(s: &str) -> Option<f64> {
+    let n = s.parse::<f64>().ok()?;
+    n.is_finite().then_some(n)
+}
+
+/// `0..=1` from a fraction or a percentage.
+fn fraction(s: &str) -> Option<f64> {
+    let n = match s.strip_suffix('%') {
+        Some(percent) => number(percent)? / 100.0,
+        None => number(s)?,
+    };
+    Some(n.clamp(0.0, 1.0))
+}
+
+fn byte(n: f64) -> u8 {
+    (n.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn alpha(alpha: Option<&str>) -> Option<u8> {
+    alpha.map_or(Some(255), |alpha| fraction(alpha).map(byte))
+}
+
+fn parse_rgb(args: &str) -> Option<Colour> {
+    let (channels, a) = arguments(args)?;
+    let channel = |s: &str| match s.strip_suffix('%') {
+        Some(percent) => number(percent).map(|n| byte(n / 100.0)),
+        None => number(s).map(|n| n.clamp(0.0, 255.0).round() as u8),
+    };
+    Some(Colour::rgba(
+        channel(channels[0])?,
+        channel(channels[1])?,
+        channel(channels[2])?,
+        alpha(a)?,
+    ))
+}
+
+fn parse_hsl(args: &str) -> Option<Colour> {
+    let (channels, a) = arguments(args)?;
+    let hue = channels[0].strip_suffix("deg").unwrap_or(channels[0]);
+    let h = number(hue)?.rem_euclid(360.0) / 360.0;
+    // Saturation and lightness are percentages, with or without the `%`
+    let percentage =
+        |s: &str| number(s.strip_suffix('%').unwrap_or(s)).map(|n| (n / 100.0).clamp(0.0, 1.0));
+    let s = percentage(channels[1])?;
+    let l = percentage(channels[2])?;
+
+    // https://www.w3.org/TR/css-color-3/#hsl-color
+    let m2 = if l <= 0.5 {
+        l * (s + 1.0)
+    } else {
+        l + s - l * s
+    };
+    let m1 = l * 2.0 - m2;
+    let hue_to_rgb = |h: f64| {
+        let h = h.rem_euclid(1.0);
+        if h * 6.0 < 1.0 {
+            m1 + (m2 - m1) * h * 6.0
+        } else if h * 2.0 < 1.0 {
+            m2
+        } else if h * 3.0 < 2.0 {
+            m1 + (m2 - m1) * (2.0 / 3.0 - h) * 6.0
+        } else {
+            m1
+        }
+    };
+    Some(Colour::rgba(
+        byte(hue_to_rgb(h + 1.0 / 3.0)),
+        byte(hue_to_rgb(h)),
+        byte(hue_to_rgb(h - 1.0 / 3.0)),
+        alpha(a)?,
+    ))
+}
+
+fn named(name: &str) -> Option<Colour> {
+    if name == "transparent" {
+        return Some(Colour::TRANSPARENT);
+    }
+    let rgb = NAMED
+        .binary_search_by_key(&name, |&(name, _)| name)
+        .ok()
+        .map(|i| NAMED[i].1)?;
+    Some(Colour::rgba(
+        (rgb >> 16) as u8,
+        (rgb >> 8) as u8,
+        rgb as u8,
+        255,
+    ))
+}
+
+/// CSS Color Module Level 4 named colours, sorted for binary search.
+const NAMED: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_css_colours() {
+        let cases = [
+            ("#f00", Colour::rgba(255, 0, 0, 255)),
+            ("#F008", Colour::rgba(255, 0, 0, 0x88)),
+            ("#1565c0", Colour::rgba(0x15, 0x65, 0xc0, 255)),
+            ("#1565c080", Colour::rgba(0x15, 0x65, 0xc0, 0x80)),
+            ("1565c0", Colour::rgba(0x15, 0x65, 0xc0, 255)),
+            ("rgb(255, 128, 0)", Colour::rgba(255, 128, 0, 255)),
+            ("rgba(255, 128, 0, 0.5)", Colour::rgba(255, 128, 0, 128)),
+            ("rgb(255 128 0 / 0.5)", Colour::rgba(255, 128, 0, 128)),
+            ("rgb(100% 50% 0% / 25%)", Colour::rgba(255, 128, 0, 64)),
+            ("hsl(0 100% 50%)", Colour::rgba(255, 0, 0, 255)),
+            ("hsl(-120 100% 50%)", Colour::rgba(0, 0, 255, 255)),
+            ("hsla(120deg, 100%, 25%, 0.5)", Colour::rgba(0, 128, 0, 128)),
+            ("cornflowerblue", Colour::rgba(0x64, 0x95, 0xed, 255)),
+            (" Black ", Colour::BLACK),
+            ("transparent", Colour::TRANSPARENT),
+        ];
+        for (input, expected) in cases {
+            let parsed = input.parse::<Colour>();
+            assert_eq!(parsed.ok(), Some(expected), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_colours() {
+        let cases = [
+            "",
+            "#",
+            "#ggg",
+            "#12345",
+            "rgb(1 2)",
+            "rgb(1, 2, 3, 4, 5)",
+            "rgb(a b c)",
+            "hsl(0 100%)",
+            "notacolour",
+        ];
+        for input in cases {
+            assert!(input.parse::<Colour>().is_err(), "{input:?}");
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use std::{env, fs, path::PathBuf, str::FromStr, time::Duration};
+
+use anyhow::{ensure, Context};
+use axum::http::HeaderName;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blob::BlobConfig, cache::CacheConfig, colour::Colour, token::TokenConfig,
+    upstream::UpstreamConfig,
+};
+
+/// Reads `name` from the environment, falling back to `default` when unset.
+pub fn env_or<T: FromStr>(name: &str, default: T) -> anyhow::Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match env::var(name) {
+        Ok(value) => value.parse().with_context(|| format!("invalid {name}")),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Reads a whole number of `unit`s from `name`, keeping `default` as is
+/// when unset so sub-unit values from the config file survive.
+pub fn env_duration(
+    name: &str,
+    default: Duration,
+    unit: fn(u64) -> Duration,
+) -> anyhow::Result<Duration> {
+    match env::var(name) {
+        Ok(value) => Ok(unit(
+            value.parse().with_context(|| format!("invalid {name}"))?,
+        )),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Settings are layered: built-in defaults, then the config file, then
+/// environment variables, then these flags.
+#[derive(Debug, Default, Parser)]
+#[clap(version, about)]
+pub struct Args {
+    /// TOML config file
+    #[clap(short, long, env = "CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Print the resolved configuration and exit
+    #[clap(long)]
+    pub print_config: bool,
+    #[clap(long)]
+    pub port: Option<u16>,
+    /// Tokens kept ready for upstream requests
+    #[clap(long)]
+    pub token_capacity: Option<usize>,
+    /// Base URL images are served from in `url` mode
+    #[clap(long)]
+    pub public_url: Option<String>,
+    #[clap(long)]
+    pub foreground: Option<Colour>,
+    #[clap(long)]
+    pub background: Option<Colour>,
+    /// Pixels around rasterised images
+    #[clap(long)]
+    pub padding: Option<u32>,
+    /// Width TeX is laid out in before wrapping
+    #[clap(long)]
+    pub layout_width: Option<f64>,
+    /// Queries to solve into the cache at startup
+    #[clap(long)]
+    pub warm_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub port: u16,
+    /// `tracing` filter, overridden by `RUST_LOG`.
+    pub log: String,
+    /// Enables `/admin` when set.
+    pub admin_token: Option<String>,
+    /// TOML file of API keys, the API is open when unset.
+    pub api_keys_file: Option<PathBuf>,
+    pub batch_concurrency: usize,
+    pub warm_concurrency: usize,
+    /// Queries solved into the cache at startup, see `warm::read_corpus`.
+    pub warm_file: Option<PathBuf>,
+    /// Upstream requests per second warm-ups may use, so live traffic keeps
+    /// the rest of `upstream.global_rate`.
+    pub warm_rate: f64,
+    /// How long in-flight requests get to finish after SIGINT or SIGTERM.
+    #[serde(with = "humantime_serde")]
+    pub drain_timeout: Duration,
+    pub tokens: TokenConfig,
+    pub render: RenderConfig,
+    pub upstream: UpstreamConfig,
+    pub cache: CacheConfig,
+    pub blobs: BlobConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: 8080,
+            log: "symbolab_rs=debug,tower_http=debug".to_owned(),
+            admin_token: None,
+            api_keys_file: None,
+            batch_concurrency: 8,
+            warm_concurrency: 4,
+            warm_file: None,
+            warm_rate: 5.0,
+            drain_timeout: Duration::from_secs(4),
+            tokens: Default::default(),
+            render: Default::default(),
+            upstream: Default::default(),
+            cache: Default::default(),
+            blobs: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderConfig {
+    /// Used when a request doesn't pick its own colours.
+    pub foreground: Colour,
+    pub background: Colour,
+    /// Terms Symbolab marks as changed in a step.
+    pub highlight: Colour,
+    /// Pixels added on each side of rasterised images.
+    pub padding: u32,
+    pub layout_width: f64,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            foreground: Colour::BLACK,
+            background: Colour::TRANSPARENT,
+            highlight: Colour::rgba(0x15, 0x65, 0xc0, 0xff),
+            padding: 200,
+            layout_width: 500.0,
+        }
+    }
+}
+
+impl RenderConfig {
+    fn apply_env(&mut self) -> anyhow::Result<()> {
+        self.foreground = env_or("DEFAULT_FOREGROUND", self.foreground)?;
+        self.background = env_or("DEFAULT_BACKGROUND", self.background)?;
+        self.highlight = env_or("DEFAULT_HIGHLIGHT", self.highlight)?;
+        self.padding = env_or("RENDER_PADDING", self.padding)?;
+        self.layout_width = env_or("LAYOUT_WIDTH", self.layout_width)?;
+        Ok(())
+    }
+}
+
+impl Config {
+    pub fn load(args: &Args) -> anyhow::Result<Self> {
+        let mut config: Config = match &args.config {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                toml::from_str(&text)
+                    .with_context(|| format!("failed to parse {}", path.display()))?
+            }
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        config.apply_args(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> anyhow::Result<()> {
+        self.port = env_or("PORT", self.port)?;
+        self.log = env_or("RUST_LOG", self.log.clone())?;
+        if let Ok(token) = env::var("ADMIN_TOKEN") {
+            self.admin_token = Some(token).filter(|token| !token.is_empty());
+        }
+        if let Ok(path) = env::var("API_KEYS_FILE") {
+            self.api_keys_file = Some(path)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from);
+        }
+        self.batch_concurrency = env_or("BATCH_CONCURRENCY", self.batch_concurrency)?;
+        self.warm_concurrency = env_or("WARM_CONCURRENCY", self.warm_concurrency)?;
+        if let Ok(path) = env::var("WARM_FILE") {
+            self.warm_file = Some(path)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from);
+        }
+        self.warm_rate = env_or("WARM_RATE", self.warm_rate)?;
+        self.drain_timeout = env_duration(
+            "DRAIN_TIMEOUT_SECS",
+            self.drain_timeout,
+            Duration::from_secs,
+        )?;
+        self.tokens.apply_env()?;
+        self.render.apply_env()?;
+        self.upstream.apply_env()?;
+        self.cache.apply_env()?;
+        self.blobs.apply_env()?;
+        Ok(())
+    }
+
+    fn apply_args(&mut self, args: &Args) {
+        if let Some(port) = args.port {
+            self.port = port;
+        }
+        if let Some(capacity) = args.token_capacity {
+            self.tokens.capacity = capacity;
+        }
+        if let Some(public_url) = &args.public_url {
+            self.blobs.public_url = public_url.clone();
+        }
+        if let Some(foreground) = args.foreground {
+            self.render.foreground = foreground;
+        }
+        if let Some(background) = args.background {
+            self.render.background = background;
+        }
+        if let Some(padding) = args.padding {
+            self.render.padding = padding;
+        }
+        if let Some(layout_width) = args.layout_width {
+            self.render.layout_width = layout_width;
+        }
+        if let Some(warm_file) = &args.warm_file {
+            self.warm_file = Some(warm_file.clone());
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.batch_concurrency > 0,
+            "batch_concurrency must be positive"
+        );
+        ensure!(
+            self.warm_concurrency > 0,
+            "warm_concurrency must be positive"
+        );
+        ensure!(self.warm_rate > 0.0, "warm_rate must be positive");
+        ensure!(self.tokens.capacity > 0, "tokens.capacity must be positive");
+        ensure!(self.tokens.buffer > 0, "tokens.buffer must be positive");
+        ensure!(
+            !self.tokens.fetch_timeout.is_zero(),
+            "tokens.fetch_timeout must be positive"
+        );
+        ensure!(
+            !self.tokens.wait_timeout.is_zero(),
+            "tokens.wait_timeout must be positive"
+        );
+        ensure!(
+            !self.tokens.user_agent.is_empty(),
+            "tokens.user_agent must not be empty"
+        );
+        ensure!(
+            self.render.layout_width > 0.0,
+            "render.layout_width must be positive"
+        );
+        ensure!(
+            self.render.padding <= 4096,
+            "render.padding must be at most 4096"
+        );
+        ensure!(
+            self.upstream.global_rate > 0.0 && self.upstream.client_rate > 0.0,
+            "upstream rates must be positive"
+        );
+        ensure!(
+            self.upstream.global_burst >= 1.0 && self.upstream.client_burst >= 1.0,
+            "upstream bursts must be at least 1"
+        );
+        ensure!(
+            self.upstream.max_concurrency > 0,
+            "upstream.max_concurrency must be positive"
+        );
+        ensure!(
+            self.upstream.breaker_threshold > 0,
+            "upstream.breaker_threshold must be positive"
+        );
+        ensure!(!self.cache.ttl.is_zero(), "cache.ttl must be positive");
+        // Otherwise an upstream error while revalidating drops an entry that
+        // was still being served a moment before
+        ensure!(
+            self.cache.stale_if_error >= self.cache.stale_while_revalidate,
+            "cache.stale_if_error must be at least cache.stale_while_revalidate"
+        );
+        if let Some(header) = &self.upstream.client_ip_header {
+            ensure!(
+                HeaderName::from_bytes(header.as_bytes()).is_ok(),
+                "upstream.client_ip_header must be a header name"
+            );
+        }
+        if let Some(token) = &self.admin_token {
+            ensure!(!token.is_empty(), "admin_token must not be empty");
+        }
+        Ok(())
+    }
+
+    /// TOML for `--print-config`, with secrets masked.
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        let mut masked = self.clone();
+        if masked.admin_token.is_some() {
+            masked.admin_token = Some("<redacted>".to_owned());
+        }
+        Ok(toml::to_string_pretty(&masked)?)
+    }
+}
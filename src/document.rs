@@ -0,0 +1,718 @@
+use std::net::SocketAddr;
+
+use anyhow::Context;
+use axum::{
+    extract::{ConnectInfo, Query},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use pathfinder_color::ColorU;
+use pathfinder_content::{
+    outline::{Contour, Outline},
+    stroke::{LineCap, LineJoin, OutlineStrokeToFill, StrokeStyle},
+};
+use pathfinder_export::{Export, FileFormat};
+use pathfinder_geometry::{rect::RectF, vector::vec2f};
+use pathfinder_renderer::{
+    paint::Paint,
+    scene::{DrawPath, Scene},
+};
+use rex::parser::ParseNode;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::{
+    accessible, clean_latex,
+    colour::Colour,
+    config::RenderConfig,
+    embed::{solution_cache_control, SolveQuery},
+    error::Result,
+    lookup_or_fetch,
+    symbolab::{PlotInfo, SolutionElement, Step, SymbolabResponse, Title},
+    tex,
+    theme::{Palette, Theme},
+    upstream::{client_key, Caller},
+    Data, Fetched, Payload, State,
+};
+
+const PLOT_WIDTH: f64 = 400.0;
+const PLOT_HEIGHT: f64 = 300.0;
+/// Space around the plot and the edge of PDF pages.
+const MARGIN: f64 = 24.0;
+
+/// A solution as a printed page shows it: the problem, then each solution
+/// with its steps nested underneath.
+pub struct Document {
+    pub problem: Option<String>,
+    pub sections: Vec<Section>,
+    /// Lines of the plot, in graph coordinates.
+    pub plot: Vec<Vec<(f64, f64)>>,
+}
+
+pub struct Section {
+    pub title: Option<String>,
+    /// The general rule the step applies, in words.
+    pub rule: Option<String>,
+    pub input: Option<String>,
+    pub result: Option<String>,
+    pub steps: Vec<Section>,
+}
+
+impl Document {
+    pub fn new(symbolab: &SymbolabResponse) -> Self {
+        Document {
+            problem: symbolab.standard_query.as_deref().map(clean_latex),
+            sections: symbolab
+                .solutions
+                .iter()
+                .flatten()
+                .map(Section::solution)
+                .collect(),
+            plot: symbolab
+                .plot_info
+                .as_ref()
+                .map(plot_lines)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The problem as plain text, for titles.
+    fn name(&self) -> String {
+        self.problem
+            .as_deref()
+            .map_or_else(|| "Solution".to_owned(), ascii)
+    }
+
+    /// Plain text for terminals, with expressions as AsciiMath.
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        if let Some(problem) = &self.problem {
+            out.push_str(&format!("{}\n\n", ascii(problem)));
+        }
+        for section in &self.sections {
+            section.text(0, &mut out);
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn html(&self, palette: &Palette, config: &RenderConfig) -> String {
+        let mut out = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n",
+            escape(&self.name()),
+            style(palette)
+        );
+        if let Some(problem) = &self.problem {
+            out.push_str("<header>");
+            out.push_str(&math_html(problem, palette.step_input, palette, config));
+            out.push_str("</header>\n");
+        }
+        for section in &self.sections {
+            section.html(0, palette, config, &mut out);
+        }
+        if let Some(bounds) = plot_bounds(&self.plot) {
+            out.push_str(&plot_svg(&self.plot, bounds, palette));
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+
+    /// PDFs are meant for paper, so `palette.background` is left out.
+    pub fn pdf(&self, palette: &Palette, config: &RenderConfig) -> anyhow::Result<Vec<u8>> {
+        let mut rows = Vec::new();
+        if let Some(problem) = &self.problem {
+            push_math(&mut rows, problem, 0, palette.step_input, palette);
+        }
+        for section in &self.sections {
+            section.pdf_rows(0, palette, &mut rows);
+        }
+        let (mut scene, [x0, y0, mut x1, mut y1]) = tex::draw(rows, config.layout_width)?;
+        if let Some(bounds) = plot_bounds(&self.plot) {
+            let origin = (x0, y1 + MARGIN);
+            draw_plot(&mut scene, &self.plot, bounds, origin, palette);
+            x1 = x1.max(x0 + PLOT_WIDTH);
+            y1 += MARGIN + PLOT_HEIGHT;
+        }
+        scene.set_view_box(RectF::from_points(
+            vec2f((x0 - MARGIN) as f32, (y0 - MARGIN) as f32),
+            vec2f((x1 + MARGIN) as f32, (y1 + MARGIN) as f32),
+        ));
+
+        let mut buf = Vec::new();
+        scene.export(&mut buf, FileFormat::PDF)?;
+        Ok(buf)
+    }
+}
+
+impl Document {
+    /// A standalone `.tex` file, each step an `align*` block under its title.
+    pub fn latex(&self) -> String {
+        let mut out = String::from(
+            "\\documentclass{article}\n\\usepackage{amsmath,amssymb}\n\\begin{document}\n\n",
+        );
+        if let Some(problem) = &self.problem {
+            out.push_str(&format!("\\[\n{}\n\\]\n\n", tex::strip_markup(problem)));
+        }
+        for section in &self.sections {
+            section.latex(&mut out);
+        }
+        out.push_str("\\end{document}\n");
+        out
+    }
+
+    /// Markdown with `$$` display maths, steps nested as ever smaller
+    /// headings.
+    pub fn markdown(&self) -> String {
+        let mut out = format!("# {}\n\n", escape_markdown(&self.name()));
+        if let Some(problem) = &self.problem {
+            push_display_math(&mut out, problem);
+        }
+        for section in &self.sections {
+            section.markdown(0, &mut out);
+        }
+        out
+    }
+}
+
+impl Section {
+    fn solution(solution: &SolutionElement) -> Self {
+        Section {
+            title: text(&solution.title),
+            rule: None,
+            input: solution.step_input.as_deref().map(clean_latex),
+            result: solution.entire_result.as_deref().map(clean_latex),
+            steps: solution.steps.iter().flatten().map(Section::step).collect(),
+        }
+    }
+
+    fn step(step: &Step) -> Self {
+        Section {
+            title: text(&step.title),
+            rule: text(&step.general_rule),
+            input: step.step_input.as_deref().map(clean_latex),
+            result: step.entire_result.as_deref().map(clean_latex),
+            steps: step.steps.iter().flatten().map(Section::step).collect(),
+        }
+    }
+
+    fn html(&self, depth: usize, palette: &Palette, config: &RenderConfig, out: &mut String) {
+        out.push_str("<section>\n");
+        if let Some(title) = &self.title {
+            let level = (depth + 2).min(6);
+            out.push_str(&format!("<h{level}>{}</h{level}>\n", escape(title)));
+        }
+        if let Some(rule) = &self.rule {
+            out.push_str(&format!("<p class=\"rule\">{}</p>\n", escape(rule)));
+        }
+        if let Some(input) = &self.input {
+            out.push_str(&math_html(input, palette.step_input, palette, config));
+        }
+        if !self.steps.is_empty() {
+            out.push_str("<ol>\n");
+            for step in &self.steps {
+                out.push_str("<li>");
+                step.html(depth + 1, palette, config, out);
+                out.push_str("</li>\n");
+            }
+            out.push_str("</ol>\n");
+        }
+        if let Some(result) = &self.result {
+            out.push_str(&math_html(result, palette.result, palette, config));
+        }
+        out.push_str("</section>\n");
+    }
+
+    fn pdf_rows(&self, depth: usize, palette: &Palette, rows: &mut Vec<ParseNode>) {
+        for text in [&self.title, &self.rule].into_iter().flatten() {
+            // The PDF is typeset entirely in XITS, so prose in scripts it
+            // doesn't cover is left to the other formats
+            if tex::is_covered(text).unwrap_or(false) {
+                let tex = format!(r"\text{{{}}}", escape_tex_text(text));
+                push_math(rows, &tex, depth, palette.title, palette);
+            } else {
+                warn!(
+                    "leaving `{}` out of the PDF, XITS has no glyphs for it",
+                    text
+                );
+            }
+        }
+        if let Some(input) = &self.input {
+            push_math(rows, input, depth, palette.step_input, palette);
+        }
+        for step in &self.steps {
+            step.pdf_rows(depth + 1, palette, rows);
+        }
+        if let Some(result) = &self.result {
+            push_math(rows, result, depth, palette.result, palette);
+        }
+    }
+
+    fn text(&self, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        if let Some(title) = &self.title {
+            out.push_str(&format!("{indent}{title}\n"));
+        }
+        if let Some(rule) = &self.rule {
+            out.push_str(&format!("{indent}({rule})\n"));
+        }
+        if let Some(input) = &self.input {
+            out.push_str(&format!("{indent}  {}\n", ascii(input)));
+        }
+        for step in &self.steps {
+            step.text(depth + 1, out);
+        }
+        if let Some(result) = &self.result {
+            out.push_str(&format!("{indent}=> {}\n", ascii(result)));
+        }
+    }
+
+    fn latex(&self, out: &mut String) {
+        let mut lines = Vec::new();
+        if let Some(title) = &self.title {
+            lines.push(format!("&\\text{{{}}}", escape_latex(title)));
+        }
+        match (&self.rule, lines.is_empty()) {
+            // `\intertext` can't open an alignment
+            (Some(rule), true) => out.push_str(&format!("\\emph{{{}}}\n\n", escape_latex(rule))),
+            (Some(rule), false) => {
+                lines.push(format!("\\intertext{{\\emph{{{}}}}}", escape_latex(rule)))
+            }
+            (None, _) => {}
+        }
+        lines.extend(
+            self.input
+                .iter()
+                .map(|input| format!("&{}", tex::strip_markup(input))),
+        );
+        if self.steps.is_empty() {
+            lines.extend(
+                self.result
+                    .iter()
+                    .map(|result| format!("&{}", tex::strip_markup(result))),
+            );
+            push_align(out, &lines);
+            return;
+        }
+        push_align(out, &lines);
+        out.push_str("\\begin{enumerate}\n");
+        for step in &self.steps {
+            out.push_str("\\item\n");
+            step.latex(out);
+        }
+        out.push_str("\\end{enumerate}\n\n");
+        if let Some(result) = &self.result {
+            push_align(out, &[format!("&{}", tex::strip_markup(result))]);
+        }
+    }
+
+    fn markdown(&self, depth: usize, out: &mut String) {
+        if let Some(title) = &self.title {
+            let title = escape_markdown(title);
+            match depth {
+                0..=4 => out.push_str(&format!("{} {title}\n\n", "#".repeat(depth + 2))),
+                _ => out.push_str(&format!("**{title}**\n\n")),
+            }
+        }
+        if let Some(rule) = &self.rule {
+            out.push_str(&format!("*{}*\n\n", escape_markdown(rule)));
+        }
+        if let Some(input) = &self.input {
+            push_display_math(out, input);
+        }
+        for step in &self.steps {
+            step.markdown(depth + 1, out);
+        }
+        if let Some(result) = &self.result {
+            push_display_math(out, result);
+        }
+    }
+}
+
+fn push_align(out: &mut String, lines: &[String]) {
+    if lines.is_empty() {
+        return;
+    }
+    out.push_str("\\begin{align*}\n");
+    for (i, line) in lines.iter().enumerate() {
+        out.push_str(line);
+        // Rows end in `\\` except the last, `\intertext` ends its own
+        if line.starts_with('&') && i + 1 < lines.len() {
+            out.push_str(" \\\\");
+        }
+        out.push('\n');
+    }
+    out.push_str("\\end{align*}\n\n");
+}
+
+fn push_display_math(out: &mut String, latex: &str) {
+    out.push_str(&format!("$$\n{}\n$$\n\n", tex::strip_markup(latex)));
+}
+
+fn escape_latex(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str(r"\textbackslash{}"),
+            '~' => out.push_str(r"\textasciitilde{}"),
+            '^' => out.push_str(r"\textasciicircum{}"),
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '#') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// AsciiMath for `latex`, or `latex` itself when ReX can't parse it.
+fn ascii(latex: &str) -> String {
+    tex::parse_tree(latex)
+        .map(|tree| tex::ascii_math(&tree))
+        .unwrap_or_else(|_| latex.to_owned())
+}
+
+fn text(title: &Option<Title>) -> Option<String> {
+    let created = title.as_ref()?.text.as_ref()?.created_text.as_deref()?;
+    Some(tex::title_text(created)).filter(|text| !text.is_empty())
+}
+
+/// Leaves out anything ReX can't parse rather than failing the document.
+fn push_math(
+    rows: &mut Vec<ParseNode>,
+    latex: &str,
+    depth: usize,
+    colour: Colour,
+    palette: &Palette,
+) {
+    let indented = format!("{}{latex}", r"\qquad ".repeat(depth));
+    match tex::styled(&indented, colour, palette.highlight) {
+        Ok(row) => rows.push(row),
+        Err(e) => warn!("leaving `{}` out of the document: {:#}", latex, e),
+    }
+}
+
+/// The expression as inline SVG, labelled for screen readers, or its source
+/// when it can't be rendered.
+fn math_html(latex: &str, colour: Colour, palette: &Palette, config: &RenderConfig) -> String {
+    let svg = tex::get_svg(latex, colour, palette.highlight, config.layout_width);
+    let spoken = tex::parse_tree(latex).map(|tree| accessible::spoken(&tree));
+    match (svg, spoken) {
+        (Ok(svg), Ok(spoken)) => {
+            // Drop the XML declaration, it's not allowed inline
+            let svg = svg.find("<svg").map_or(svg.as_str(), |start| &svg[start..]);
+            format!(
+                "<div class=\"math\" role=\"img\" aria-label=\"{}\">{svg}</div>\n",
+                escape(&spoken)
+            )
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            warn!("failed to render `{}` for a document: {:#}", latex, e);
+            format!("<div class=\"math\"><code>{}</code></div>\n", escape(latex))
+        }
+    }
+}
+
+fn style(palette: &Palette) -> String {
+    format!(
+        "body{{background:{};color:{};font-family:serif;max-width:50rem;margin:2rem auto;padding:0 1rem}}\
+         .math{{margin:.5rem 0;overflow-x:auto}}.math svg{{max-width:100%;height:auto}}\
+         .rule{{font-style:italic}}ol{{padding-left:1.5rem}}",
+        palette.background, palette.title
+    )
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Drops the characters `\text` would treat as commands.
+fn escape_tex_text(text: &str) -> String {
+    text.chars()
+        .filter(|c| {
+            !matches!(
+                c,
+                '\\' | '{' | '}' | '$' | '^' | '_' | '%' | '#' | '&' | '~'
+            )
+        })
+        .collect()
+}
+
+/// Symbolab doesn't document `linesToDraw`, so any list of `[x, y]` pairs
+/// or `{"x": .., "y": ..}` objects in it is taken as a line.
+fn plot_lines(plot: &PlotInfo) -> Vec<Vec<(f64, f64)>> {
+    let mut lines = Vec::new();
+    for value in plot.lines_to_draw.iter().flatten().flatten() {
+        collect_lines(value, &mut lines);
+    }
+    lines
+}
+
+fn collect_lines(value: &Value, lines: &mut Vec<Vec<(f64, f64)>>) {
+    match value {
+        Value::Array(items) => match items.iter().map(point).collect::<Option<Vec<_>>>() {
+            Some(points) if points.len() >= 2 => lines.push(points),
+            _ => {
+                for item in items {
+                    collect_lines(item, lines);
+                }
+            }
+        },
+        Value::Object(map) => {
+            for item in map.values() {
+                collect_lines(item, lines);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn point(value: &Value) -> Option<(f64, f64)> {
+    let (x, y) = match value {
+        Value::Array(pair) if pair.len() == 2 => (pair[0].as_f64()?, pair[1].as_f64()?),
+        Value::Object(map) => (map.get("x")?.as_f64()?, map.get("y")?.as_f64()?),
+        _ => return None,
+    };
+    Some((x, y)).filter(|_| x.is_finite() && y.is_finite())
+}
+
+/// `[x0, y0, x1, y1]` around every point, `None` when there's nothing to
+/// plot.
+fn plot_bounds(lines: &[Vec<(f64, f64)>]) -> Option<[f64; 4]> {
+    let mut points = lines.iter().flatten();
+    let &(x, y) = points.next()?;
+    let [x0, y0, x1, y1] = points.fold([x, y, x, y], |[x0, y0, x1, y1], &(x, y)| {
+        [x0.min(x), y0.min(y), x1.max(x), y1.max(y)]
+    });
+    // A flat line still needs some height to be drawn in
+    let widen = |lo: f64, hi: f64| {
+        if hi - lo > f64::EPSILON {
+            (lo, hi)
+        } else {
+            (lo - 1.0, hi + 1.0)
+        }
+    };
+    let (x0, x1) = widen(x0, x1);
+    let (y0, y1) = widen(y0, y1);
+    Some([x0, y0, x1, y1])
+}
+
+/// Graph coordinates to a `PLOT_WIDTH` by `PLOT_HEIGHT` box with y down.
+fn project([x0, y0, x1, y1]: [f64; 4], (x, y): (f64, f64)) -> (f64, f64) {
+    (
+        (x - x0) / (x1 - x0) * PLOT_WIDTH,
+        PLOT_HEIGHT - (y - y0) / (y1 - y0) * PLOT_HEIGHT,
+    )
+}
+
+/// The axes that fall inside `bounds`, as pairs of projected end points.
+fn axes(bounds: [f64; 4]) -> Vec<[(f64, f64); 2]> {
+    let [x0, y0, x1, y1] = bounds;
+    let mut axes = Vec::new();
+    if (y0..=y1).contains(&0.0) {
+        axes.push([project(bounds, (x0, 0.0)), project(bounds, (x1, 0.0))]);
+    }
+    if (x0..=x1).contains(&0.0) {
+        axes.push([project(bounds, (0.0, y0)), project(bounds, (0.0, y1))]);
+    }
+    axes
+}
+
+fn plot_svg(lines: &[Vec<(f64, f64)>], bounds: [f64; 4], palette: &Palette) -> String {
+    let mut svg = format!(
+        "<figure><svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {PLOT_WIDTH} {PLOT_HEIGHT}\" width=\"{PLOT_WIDTH}\" height=\"{PLOT_HEIGHT}\" fill=\"none\">"
+    );
+    for [(ax, ay), (bx, by)] in axes(bounds) {
+        svg.push_str(&format!(
+            "<line x1=\"{ax:.2}\" y1=\"{ay:.2}\" x2=\"{bx:.2}\" y2=\"{by:.2}\" stroke=\"{}\"/>",
+            palette.title
+        ));
+    }
+    for line in lines {
+        let points = line
+            .iter()
+            .map(|&point| {
+                let (x, y) = project(bounds, point);
+                format!("{x:.2},{y:.2}")
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        svg.push_str(&format!(
+            "<polyline points=\"{points}\" stroke=\"{}\" stroke-width=\"2\"/>",
+            palette.highlight
+        ));
+    }
+    svg.push_str("</svg></figure>\n");
+    svg
+}
+
+fn draw_plot(
+    scene: &mut Scene,
+    lines: &[Vec<(f64, f64)>],
+    bounds: [f64; 4],
+    (left, top): (f64, f64),
+    palette: &Palette,
+) {
+    let axes = axes(bounds)
+        .into_iter()
+        .map(|axis| axis.to_vec())
+        .collect::<Vec<_>>();
+    let lines = lines
+        .iter()
+        .map(|line| line.iter().map(|&point| project(bounds, point)).collect())
+        .collect::<Vec<_>>();
+    for (paths, colour, width) in [(axes, palette.title, 1.0), (lines, palette.highlight, 2.0)] {
+        let mut outline = Outline::new();
+        for path in paths {
+            let mut contour = Contour::new();
+            for (x, y) in path {
+                contour.push_endpoint(vec2f((left + x) as f32, (top + y) as f32));
+            }
+            outline.push_contour(contour);
+        }
+        let mut stroke = OutlineStrokeToFill::new(
+            &outline,
+            StrokeStyle {
+                line_width: width,
+                line_cap: LineCap::Butt,
+                line_join: LineJoin::Bevel,
+            },
+        );
+        stroke.offset();
+        let paint = scene.push_paint(&Paint::from_color(ColorU::new(
+            colour.r, colour.g, colour.b, colour.a,
+        )));
+        scene.push_draw_path(DrawPath::new(stroke.into_outline(), paint));
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Html,
+    Pdf,
+    Tex,
+    Markdown,
+}
+
+impl Format {
+    fn path(self) -> &'static str {
+        match self {
+            Format::Html => "/solve.html",
+            Format::Pdf => "/solve.pdf",
+            Format::Tex => "/solve.tex",
+            Format::Markdown => "/solve.md",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Html => "text/html; charset=utf-8",
+            Format::Pdf => "application/pdf",
+            Format::Tex => "application/x-tex; charset=utf-8",
+            Format::Markdown => "text/markdown; charset=utf-8",
+        }
+    }
+}
+
+/// `GET /solve.{html,pdf,tex,md}?q=...` and `POST` with the body `POST /`
+/// takes, the whole solution as one document.
+pub fn router() -> Router {
+    let mut router = Router::new();
+    for format in [Format::Html, Format::Pdf, Format::Tex, Format::Markdown] {
+        router = router.route(
+            format.path(),
+            get(
+                move |Query(query): Query<SolveQuery>,
+                      Extension(state): Extension<State>,
+                      ConnectInfo(addr): ConnectInfo<SocketAddr>,
+                      headers: HeaderMap| {
+                    let client = client_key(&headers, addr, &state.config.upstream);
+                    export(state, client, query.payload(), format)
+                },
+            )
+            .post(
+                move |Json(payload): Json<Payload>,
+                      Extension(state): Extension<State>,
+                      ConnectInfo(addr): ConnectInfo<SocketAddr>,
+                      headers: HeaderMap| {
+                    let client = client_key(&headers, addr, &state.config.upstream);
+                    export(state, client, payload, format)
+                },
+            ),
+        );
+    }
+    router
+}
+
+async fn export(
+    state: State,
+    client: String,
+    payload: Payload,
+    format: Format,
+) -> Result<Response> {
+    let payload = payload.normalize()?;
+    let palette = match format {
+        // Light text meant for a dark background would vanish on paper
+        Format::Pdf => Payload {
+            theme: Some(Theme::Light),
+            background: None,
+            ..payload.clone()
+        }
+        .palette(&state.config.render),
+        _ => payload.palette(&state.config.render),
+    };
+    // Documents typeset their own maths, so the images `/solve` renders
+    // would go to waste
+    let (symbolab, stale) = match lookup_or_fetch(&state, Caller::Client(&client), &payload).await?
+    {
+        Fetched::Cached(data) => (data.symbolab, data.stale),
+        Fetched::Upstream(symbolab) => {
+            let data = Data::unrendered((*symbolab).clone());
+            state.response_cache.insert(payload, data).await;
+            (*symbolab, false)
+        }
+    };
+    let cache_control = solution_cache_control(&state, stale);
+    let document = Document::new(&symbolab);
+    let config = state.config.clone();
+    // Every expression is typeset, which takes a while for long solutions
+    let body = tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<u8>> {
+        Ok(match format {
+            Format::Html => document.html(&palette, &config.render).into_bytes(),
+            Format::Pdf => document.pdf(&palette, &config.render)?,
+            Format::Tex => document.latex().into_bytes(),
+            Format::Markdown => document.markdown().into_bytes(),
+        })
+    })
+    .await
+    .context("failed to build document")??;
+    let disposition = format!(
+        "inline; filename=\"solution{}\"",
+        &format.path()["/solve".len()..]
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_owned()),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::CACHE_CONTROL, cache_control),
+        ],
+        body,
+    )
+        .into_response())
+}
@@ -0,0 +1,174 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Query},
+    http::{header, HeaderMap},
+    response::Response,
+    Extension, Json,
+};
+use serde::Deserialize;
+
+use crate::{
+    blob::ImageMode,
+    clean_latex,
+    colour::Colour,
+    error::{Error, Result},
+    etag, get_webp, solve_cached,
+    symbolab::Language,
+    tex,
+    theme::Theme,
+    upstream::{client_key, Caller},
+    Payload, State,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct SolveQuery {
+    q: String,
+    fg: Option<String>,
+    bg: Option<String>,
+    theme: Option<Theme>,
+    #[serde(default)]
+    lang: Language,
+    #[serde(default)]
+    images: ImageMode,
+}
+
+impl SolveQuery {
+    pub fn payload(self) -> Payload {
+        Payload {
+            query: self.q,
+            foreground: self.fg,
+            background: self.bg,
+            theme: self.theme,
+            colours: Default::default(),
+            language: self.lang,
+            options: Default::default(),
+            images: self.images,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenderQuery {
+    tex: String,
+    fg: Option<String>,
+    bg: Option<String>,
+}
+
+/// `GET /solve?q=...`, the cacheable equivalent of `POST /`.
+pub async fn solve(
+    Query(query): Query<SolveQuery>,
+    Extension(state): Extension<State>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let payload = query.payload().normalize()?;
+    let key = serde_json::to_vec(&payload).map_err(anyhow::Error::from)?;
+    let client = client_key(&headers, addr, &state.config.upstream);
+    let data = solve_cached(&state, Caller::Client(&client), payload).await?;
+    // Weak, since the cache flags and image URLs can change the body without
+    // changing the solution
+    let upstream = serde_json::to_vec(&data.symbolab).map_err(anyhow::Error::from)?;
+    let version = etag::render_version(&state.config.render);
+    let etag = etag::weak(&[&key, &upstream, version.as_bytes()]);
+    let cache_control = solution_cache_control(&state, data.stale);
+    Ok(etag::respond(&headers, &etag, &cache_control, || {
+        Json(data)
+    }))
+}
+
+/// Stale solutions are revalidated on every use, fresh ones kept as long as
+/// the response cache keeps them.
+pub fn solution_cache_control(state: &State, stale: bool) -> String {
+    let scope = etag::scope(&state.keys);
+    if stale {
+        format!("{scope}, max-age=0, must-revalidate")
+    } else {
+        format!("{scope}, max-age={}", state.response_cache.ttl().as_secs())
+    }
+}
+
+fn render_cache_control(state: &State) -> String {
+    format!(
+        "{}, max-age={}",
+        etag::scope(&state.keys),
+        etag::RENDERED_MAX_AGE
+    )
+}
+
+/// `GET /render.svg?tex=...`, typesets a single expression for `<img>` tags.
+pub async fn render_svg(
+    Query(query): Query<RenderQuery>,
+    Extension(state): Extension<State>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let render = &state.config.render;
+    let fg = colour_or(query.fg.as_deref(), "fg", render.foreground)?;
+    let etag = etag::weak(&[
+        b"svg",
+        query.tex.as_bytes(),
+        fg.to_hex().as_bytes(),
+        etag::render_version(render).as_bytes(),
+    ]);
+    if etag::matches(&headers, &etag) {
+        return Ok(etag::respond(
+            &headers,
+            &etag,
+            &render_cache_control(&state),
+            || (),
+        ));
+    }
+    let svg = tex::get_svg(
+        &clean_latex(&query.tex),
+        fg,
+        render.highlight,
+        render.layout_width,
+    )?;
+    Ok(etag::respond(
+        &headers,
+        &etag,
+        &render_cache_control(&state),
+        || ([(header::CONTENT_TYPE, "image/svg+xml")], svg),
+    ))
+}
+
+/// `GET /render.webp?tex=...`, same as `render.svg` but rasterised.
+pub async fn render_webp(
+    Query(query): Query<RenderQuery>,
+    Extension(state): Extension<State>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let render = &state.config.render;
+    let fg = colour_or(query.fg.as_deref(), "fg", render.foreground)?;
+    let bg = colour_or(query.bg.as_deref(), "bg", render.background)?;
+    let etag = etag::weak(&[
+        b"webp",
+        query.tex.as_bytes(),
+        fg.to_hex().as_bytes(),
+        bg.to_hex().as_bytes(),
+        etag::render_version(render).as_bytes(),
+    ]);
+    if etag::matches(&headers, &etag) {
+        return Ok(etag::respond(
+            &headers,
+            &etag,
+            &render_cache_control(&state),
+            || (),
+        ));
+    }
+    let webp = get_webp(&clean_latex(&query.tex), fg, render.highlight, bg, render)?;
+    Ok(etag::respond(
+        &headers,
+        &etag,
+        &render_cache_control(&state),
+        || ([(header::CONTENT_TYPE, "image/webp")], webp),
+    ))
+}
+
+fn colour_or(colour: Option<&str>, field: &str, default: Colour) -> Result<Colour> {
+    colour.map_or(Ok(default), |colour| {
+        colour
+            .parse()
+            .map_err(|e| Error::BadRequest(format!("{field}: {e}")))
+    })
+}
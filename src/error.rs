@@ -1,20 +1,78 @@
+use std::time::Duration;
+
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
 };
 
-pub struct Error(pub anyhow::Error);
+pub enum Error {
+    Internal(anyhow::Error),
+    BadRequest(String),
+    Unauthorized,
+    Forbidden(String),
+    NotFound,
+    Unavailable { retry_after: Duration },
+    RateLimited { retry_after: Duration },
+    QuotaExceeded { retry_after: Duration },
+}
+
+impl Error {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::Unauthorized => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::Unavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Error::RateLimited { .. } | Error::QuotaExceeded { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+        }
+    }
+
+    /// What the client gets told, internal errors are logged instead.
+    pub fn message(&self) -> String {
+        match self {
+            Error::Internal(err) => {
+                tracing::error!("{:#}", err);
+                "Something went wrong".to_owned()
+            }
+            Error::BadRequest(message) => message.clone(),
+            Error::Unauthorized => "Missing or invalid credentials".to_owned(),
+            Error::Forbidden(message) => message.clone(),
+            Error::NotFound => "Not found".to_owned(),
+            Error::Unavailable { .. } => "Service unavailable, try again later".to_owned(),
+            Error::RateLimited { .. } => "Too many requests, slow down".to_owned(),
+            Error::QuotaExceeded { .. } => "Daily quota exceeded".to_owned(),
+        }
+    }
+}
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        tracing::error!("{:#}", self.0);
-        (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong").into_response()
+        let status = self.status();
+        let message = self.message();
+        match self {
+            Error::Unavailable { retry_after }
+            | Error::RateLimited { retry_after }
+            | Error::QuotaExceeded { retry_after } => (
+                status,
+                [(
+                    header::RETRY_AFTER,
+                    retry_after.as_secs().max(1).to_string(),
+                )],
+                message,
+            )
+                .into_response(),
+            _ => (status, message).into_response(),
+        }
     }
 }
 
 impl From<anyhow::Error> for Error {
     fn from(err: anyhow::Error) -> Self {
-        Error(err)
+        Error::Internal(err)
     }
 }
 pub type Result<T> = core::result::Result<T, Error>;
@@ -0,0 +1,98 @@
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+
+use crate::{config::RenderConfig, keys::ApiKeys};
+
+/// Long enough that caches keep content-addressed responses for good.
+pub const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Seconds renders addressed by their input rather than their content are
+/// cached for, since they change when the renderer does.
+pub const RENDERED_MAX_AGE: u64 = 24 * 60 * 60;
+
+/// `public` unless responses depend on an API key. Shared caches would
+/// otherwise hand them to callers without one, and their hits would skip
+/// the key's quota.
+pub fn scope(keys: &ApiKeys) -> &'static str {
+    if keys.enabled() {
+        "private"
+    } else {
+        "public"
+    }
+}
+
+/// The build's font and rendering crates, see `build.rs`.
+static RENDERER: Lazy<String> = Lazy::new(|| {
+    etag(&[
+        env!("CARGO_PKG_VERSION").as_bytes(),
+        env!("RENDER_DEPS").as_bytes(),
+        include_bytes!("../rex-xits.otf"),
+    ])
+});
+
+/// Everything besides the request that goes into a rendered image, to
+/// include in its validator.
+pub fn render_version(config: &RenderConfig) -> String {
+    etag(&[
+        RENDERER.as_bytes(),
+        config.highlight.to_hex().as_bytes(),
+        &config.layout_width.to_le_bytes(),
+        &config.padding.to_le_bytes(),
+    ])
+}
+
+/// A strong validator for the given parts, quoted as the header expects.
+pub fn etag(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    format!("\"{:x}\"", hasher.finalize())
+}
+
+/// A weak validator for the given parts, for responses that are equivalent
+/// whenever the parts are but may differ byte for byte.
+pub fn weak(parts: &[&[u8]]) -> String {
+    format!("W/{}", etag(parts))
+}
+
+/// Whether `If-None-Match` already names `etag`, in which case the client's
+/// copy is current. Compared weakly, as that header is.
+pub fn matches(headers: &HeaderMap, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|candidate| candidate.trim().trim_start_matches("W/"))
+        .any(|candidate| candidate == "*" || candidate == etag)
+}
+
+/// Answers with a 304 if the client already has `etag`, otherwise with
+/// `body()` and the caching headers.
+pub fn respond<B: IntoResponse>(
+    headers: &HeaderMap,
+    etag: &str,
+    cache_control: &str,
+    body: impl FnOnce() -> B,
+) -> Response {
+    let mut res = if matches(headers, etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        body().into_response()
+    };
+    let res_headers = res.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        res_headers.insert(header::ETAG, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(cache_control) {
+        res_headers.insert(header::CACHE_CONTROL, value);
+    }
+    res
+}
@@ -0,0 +1,62 @@
+use anyhow::{anyhow, Context};
+use once_cell::sync::Lazy;
+use usvg::{fontdb, NodeExt};
+
+use crate::{colour::Colour, document::escape, tex};
+
+/// Installed fonts, for text XITS has no glyphs for. The image installs Noto
+/// for the scripts Symbolab writes explanations in.
+pub static FONTS: Lazy<fontdb::Database> = Lazy::new(|| {
+    let mut fonts = fontdb::Database::new();
+    fonts.load_system_fonts();
+    fonts.set_sans_serif_family("Noto Sans");
+    fonts
+});
+
+/// `usvg` options that find glyphs in [`FONTS`].
+pub fn with_fonts<T>(f: impl FnOnce(&usvg::OptionsRef) -> T) -> T {
+    let opt = usvg::Options::default();
+    let mut opt = opt.to_ref();
+    opt.fontdb = &FONTS;
+    f(&opt)
+}
+
+/// Sets `text` in a system font as an SVG the size ReX would have drawn it,
+/// for titles in scripts XITS doesn't cover.
+pub fn text_svg(text: &str, colour: Colour) -> anyhow::Result<String> {
+    let size = (*FONT_SIZE).map_err(|e| anyhow!(e))?;
+    let text = escape(text);
+    let fill = format!(
+        r##"fill="#{:02x}{:02x}{:02x}" fill-opacity="{}""##,
+        colour.r,
+        colour.g,
+        colour.b,
+        colour.a as f64 / 255.0
+    );
+    // Laid out once to find where the glyphs end up, since that depends on
+    // which fonts had them
+    let draft = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="1" height="1"><text font-family="sans-serif" font-size="{size}" {fill}>{text}</text></svg>"#
+    );
+    let tree = with_fonts(|opt| usvg::Tree::from_data(draft.as_bytes(), opt))?;
+    let bbox = tree
+        .root()
+        .calculate_bbox()
+        .context("no installed font has glyphs for the text")?;
+    let (x, y, width, height) = (bbox.x(), bbox.y(), bbox.width(), bbox.height());
+    Ok(format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="{x} {y} {width} {height}"><text font-family="sans-serif" font-size="{size}" {fill}>{text}</text></svg>"#
+    ))
+}
+
+/// Font size matching what ReX sets text at, from the height it gives a
+/// sample.
+static FONT_SIZE: Lazy<Result<f64, &'static str>> = Lazy::new(|| {
+    let sample = tex::styled(r"\text{Hg}", Colour::BLACK, Colour::BLACK)
+        .map_err(|_| "failed to parse font size sample")?;
+    // Wide enough that the sample is never broken
+    let (_, [_, y0, _, y1]) =
+        tex::draw(vec![sample], 1000.0).map_err(|_| "failed to lay out font size sample")?;
+    // "Hg" spans ascender to descender, about 0.9em in XITS
+    Ok((y1 - y0) / 0.9)
+});
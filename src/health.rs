@@ -0,0 +1,71 @@
+use std::sync::atomic::Ordering;
+
+use axum::{http::StatusCode, response::IntoResponse, Extension, Json};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{get_webp, tex, State};
+
+const CANARY: &str = "x^2";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Readiness {
+    ready: bool,
+    tokens_ready: usize,
+    tokens_stuck: bool,
+    font_loaded: bool,
+    renderer_ok: bool,
+    circuit: &'static str,
+}
+
+/// `GET /healthz`, the process is up and serving requests.
+pub async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// `GET /readyz`, whether this instance can serve. Fails only for problems
+/// local to the instance: a missing font, a broken renderer or a token
+/// factory that stopped making progress. An empty pool or an open circuit
+/// are reported but still pass, since they affect every instance alike and
+/// cached solutions can still be served.
+pub async fn readyz(Extension(state): Extension<State>) -> impl IntoResponse {
+    let tokens_ready = state.tokens.ready.load(Ordering::Relaxed);
+    let tokens_stuck = state.tokens.stuck();
+    let breaker = state.upstream.breaker_state();
+    let render = state.config.render.clone();
+    let (font_loaded, renderer_ok) = tokio::task::spawn_blocking(move || {
+        let font_loaded = tex::load_font().is_ok();
+        let renderer_ok = match get_webp(
+            CANARY,
+            render.foreground,
+            render.highlight,
+            render.background,
+            &render,
+        ) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("canary render failed: {:#}", e);
+                false
+            }
+        };
+        (font_loaded, renderer_ok)
+    })
+    .await
+    .unwrap_or_default();
+
+    let readiness = Readiness {
+        ready: font_loaded && renderer_ok && !tokens_stuck,
+        tokens_ready,
+        tokens_stuck,
+        font_loaded,
+        renderer_ok,
+        circuit: breaker.name(),
+    };
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
+}
@@ -0,0 +1,355 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{ensure, Context};
+use axum::{
+    body::Body,
+    http::{header, request::Parts, HeaderMap, HeaderValue, Request, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use metrics::increment_counter;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn, Span};
+
+use crate::{
+    error::Error,
+    upstream::{Bucket, MAX_BATCH_WAIT},
+    State,
+};
+
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKey {
+    pub key: String,
+    /// Shown in logs, metrics and usage reports instead of the key.
+    pub name: String,
+    /// Requests per second.
+    pub rate: f64,
+    pub burst: f64,
+    /// Requests per UTC day, unlimited when unset.
+    pub daily_quota: Option<u64>,
+    /// Browser origins allowed to use the key, any when empty.
+    #[serde(default)]
+    pub origins: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeysFile {
+    keys: Vec<ApiKey>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    pub today: u64,
+    pub total: u64,
+    pub rate_limited: u64,
+    pub quota_exceeded: u64,
+}
+
+#[derive(Debug)]
+struct KeyState {
+    key: ApiKey,
+    bucket: Bucket,
+    /// Days since the epoch `usage.today` counts.
+    day: u64,
+    usage: Usage,
+}
+
+impl KeyState {
+    /// Starts a new day's count if the last request was on an earlier one,
+    /// then refuses if the quota is used up.
+    fn check_quota(&mut self) -> Result<(), Error> {
+        let day = today();
+        if self.day != day {
+            self.day = day;
+            self.usage.today = 0;
+        }
+        if let Some(quota) = self.key.daily_quota {
+            if self.usage.today >= quota {
+                self.usage.quota_exceeded += 1;
+                increment_counter!("symbolab_api_key_requests_total", "key" => self.key.name.clone(), "outcome" => "quota_exceeded");
+                return Err(Error::QuotaExceeded {
+                    retry_after: until_tomorrow(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn count(&mut self) {
+        self.usage.today += 1;
+        self.usage.total += 1;
+        increment_counter!("symbolab_api_key_requests_total", "key" => self.key.name.clone(), "outcome" => "ok");
+    }
+}
+
+/// The key a request was admitted with, for handlers that do more than one
+/// request's worth of work.
+#[derive(Debug, Clone)]
+pub struct Admitted(pub String);
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyUsage {
+    pub name: String,
+    pub daily_quota: Option<u64>,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+/// API keys loaded from a TOML file of `[[keys]]` tables. Without a file
+/// every request is let through, as before keys existed.
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    keys: Option<Mutex<HashMap<String, KeyState>>>,
+}
+
+impl ApiKeys {
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let Some(path) = path else {
+            warn!("no API keys configured, the API is open to everyone");
+            return Ok(Self::default());
+        };
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let file: KeysFile =
+            toml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))?;
+        let mut keys = HashMap::new();
+        for key in file.keys {
+            ensure!(!key.key.is_empty(), "key `{}` is empty", key.name);
+            ensure!(
+                key.rate > 0.0 && key.burst >= 1.0,
+                "key `{}` needs a positive rate and a burst of at least 1",
+                key.name
+            );
+            let state = KeyState {
+                bucket: Bucket::new(key.burst),
+                day: today(),
+                usage: Usage::default(),
+                key: key.clone(),
+            };
+            ensure!(
+                keys.insert(key.key, state).is_none(),
+                "key `{}` is listed twice",
+                key.name
+            );
+        }
+        Ok(Self {
+            keys: Some(Mutex::new(keys)),
+        })
+    }
+
+    /// Whether any key may be used from `origin`, for CORS preflights, which
+    /// don't carry the key header.
+    pub fn origin_allowed(&self, origin: &HeaderValue, parts: &Parts) -> bool {
+        let Some(keys) = &self.keys else {
+            return true;
+        };
+        let keys = keys.lock().unwrap();
+        match provided_key(&parts.headers, parts.uri.query()) {
+            Some(provided) => keys
+                .get(&provided)
+                .is_some_and(|state| allows(&state.key, Some(origin))),
+            None => keys.values().any(|state| allows(&state.key, Some(origin))),
+        }
+    }
+
+    /// Whether requests need a key, in which case responses depend on it.
+    pub fn enabled(&self) -> bool {
+        self.keys.is_some()
+    }
+
+    /// Checks the key and counts the request against its limits, returning
+    /// the key when there are any.
+    fn admit(
+        &self,
+        provided: Option<&str>,
+        origin: Option<&HeaderValue>,
+    ) -> Result<Option<Admitted>, Error> {
+        let Some(keys) = &self.keys else {
+            return Ok(None);
+        };
+        let mut keys = keys.lock().unwrap();
+        let (provided, state) = provided
+            .and_then(|provided| Some((provided, keys.get_mut(provided)?)))
+            .ok_or(Error::Unauthorized)?;
+        if !allows(&state.key, origin) {
+            return Err(Error::Forbidden(
+                "This key can't be used from this origin".to_owned(),
+            ));
+        }
+
+        state.check_quota()?;
+        if let Err(retry_after) = state.bucket.try_take(state.key.rate, state.key.burst) {
+            state.usage.rate_limited += 1;
+            increment_counter!("symbolab_api_key_requests_total", "key" => state.key.name.clone(), "outcome" => "rate_limited");
+            return Err(Error::RateLimited { retry_after });
+        }
+        state.count();
+        Ok(Some(Admitted(provided.to_owned())))
+    }
+
+    /// Counts one more request's worth of work against an admitted key, for
+    /// each item of a batch after the first. Waits for the key's rate limit
+    /// instead of failing, so batches are paced rather than cut short, unless
+    /// the wait would exceed [`MAX_BATCH_WAIT`].
+    pub async fn charge(&self, admitted: &Admitted) -> Result<(), Error> {
+        let wait = {
+            let Some(keys) = &self.keys else {
+                return Ok(());
+            };
+            let mut keys = keys.lock().unwrap();
+            let Some(state) = keys.get_mut(&admitted.0) else {
+                return Err(Error::Unauthorized);
+            };
+            state.check_quota()?;
+            let wait = state
+                .bucket
+                .take(state.key.rate, state.key.burst, MAX_BATCH_WAIT)
+                .map_err(|retry_after| {
+                    state.usage.rate_limited += 1;
+                    increment_counter!("symbolab_api_key_requests_total", "key" => state.key.name.clone(), "outcome" => "rate_limited");
+                    Error::RateLimited { retry_after }
+                })?;
+            state.count();
+            wait
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+
+    pub fn usage(&self) -> Vec<KeyUsage> {
+        let Some(keys) = &self.keys else {
+            return Vec::new();
+        };
+        let keys = keys.lock().unwrap();
+        let mut usage = keys
+            .values()
+            .map(|state| KeyUsage {
+                name: state.key.name.clone(),
+                daily_quota: state.key.daily_quota,
+                usage: Usage {
+                    today: if state.day == today() {
+                        state.usage.today
+                    } else {
+                        0
+                    },
+                    ..state.usage.clone()
+                },
+            })
+            .collect::<Vec<_>>();
+        usage.sort_by(|a, b| a.name.cmp(&b.name));
+        usage
+    }
+}
+
+fn allows(key: &ApiKey, origin: Option<&HeaderValue>) -> bool {
+    match origin.and_then(|origin| origin.to_str().ok()) {
+        Some(origin) => {
+            key.origins.is_empty() || key.origins.iter().any(|allowed| allowed == origin)
+        }
+        // Not a browser, or a same-origin request
+        None => true,
+    }
+}
+
+/// The key from `X-Api-Key`, or the `api_key` query parameter for clients
+/// that can't set headers. Keys are expected to be URL-safe.
+fn provided_key(headers: &HeaderMap, query: Option<&str>) -> Option<String> {
+    headers
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+        .or_else(|| {
+            query?
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("api_key="))
+                .map(str::to_owned)
+        })
+}
+
+/// Span for each request, like `TraceLayer`'s default but with the `api_key`
+/// query parameter masked so keys don't end up in logs.
+pub fn request_span(req: &Request<Body>) -> Span {
+    tracing::debug_span!(
+        "request",
+        method = %req.method(),
+        uri = %redact(req.uri()),
+        version = ?req.version(),
+    )
+}
+
+fn redact(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.to_string();
+    };
+    let query = query
+        .split('&')
+        .map(|pair| {
+            if pair.starts_with("api_key=") {
+                "api_key=redacted"
+            } else {
+                pair
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{}?{query}", uri.path())
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / DAY
+}
+
+fn until_tomorrow() -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Duration::from_secs(DAY - now % DAY)
+}
+
+/// Rejects requests without a valid key, from an origin the key doesn't
+/// allow, or over the key's rate limit or daily quota. Admitted requests
+/// carry their key as an [`Admitted`] extension.
+pub async fn authenticate<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let Some(state) = req.extensions().get::<State>() else {
+        // Only possible if the layers were reordered, don't let that make
+        // every route public
+        error!("no state for authentication, rejecting request");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let provided = provided_key(req.headers(), req.uri().query());
+    let origin = req.headers().get(header::ORIGIN);
+    match state.keys.admit(provided.as_deref(), origin) {
+        Ok(admitted) => {
+            if let Some(admitted) = admitted {
+                req.extensions_mut().insert(admitted);
+            }
+            next.run(req).await
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+/// `GET /admin/keys`
+pub async fn usage(Extension(state): Extension<State>) -> Json<Vec<KeyUsage>> {
+    Json(state.keys.usage())
+}
@@ -0,0 +1,807 @@
+use anyhow::Context;
+use axum::{
+    extract::ConnectInfo,
+    http::HeaderMap,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::{
+    net::{Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tiny_skia::Color;
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+use tower_http::{
+    compression::CompressionLayer,
+    cors::{AllowOrigin, Any, CorsLayer},
+    trace::TraceLayer,
+};
+use tracing::{error, info, warn};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+pub mod accessible;
+
+mod admin;
+
+mod batch;
+
+mod blob;
+use blob::*;
+
+mod cache;
+use cache::*;
+
+pub mod colour;
+use colour::Colour;
+
+pub mod config;
+use config::{Args, Config, RenderConfig};
+
+pub mod document;
+
+mod embed;
+
+pub mod error;
+use error::*;
+
+mod etag;
+
+mod fallback;
+
+mod health;
+
+mod keys;
+use keys::ApiKeys;
+
+mod metrics;
+
+mod normalize;
+
+mod shutdown;
+
+mod stream;
+
+pub mod symbolab;
+use symbolab::*;
+
+pub mod tex;
+
+pub mod theme;
+use theme::{Palette, RoleColours, Theme};
+
+pub mod token;
+use token::*;
+
+pub mod upstream;
+use upstream::*;
+
+pub mod warm;
+
+/// Runs the HTTP server until SIGINT or SIGTERM.
+pub async fn serve(args: Args) -> anyhow::Result<()> {
+    let config = Config::load(&args)?;
+    if args.print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+
+    // console_subscriber::init();
+    tracing_subscriber::registry()
+        // .with(console_subscriber::spawn())
+        .with(tracing_subscriber::EnvFilter::new(&config.log))
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    let prometheus = metrics::install()?;
+    let (state, factory) = State::new(config).await?;
+    let config = state.config.clone();
+    if let Some(path) = &config.warm_file {
+        let payloads = warm::read_corpus(path)?;
+        let state = state.clone();
+        tokio::spawn(async move { warm::warm(&state, payloads).await });
+    }
+    let keys = state.keys.clone();
+    let response_cache = state.response_cache.clone();
+
+    let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, config.port));
+    let drain_timeout = config.drain_timeout;
+    let api = Router::new()
+        .route("/", post(handler))
+        .route("/batch", post(batch::handler))
+        .route("/stream", post(stream::handler))
+        .route("/solve", get(embed::solve))
+        .merge(document::router())
+        .route("/render.svg", get(embed::render_svg))
+        .route("/render.webp", get(embed::render_webp))
+        .route_layer(axum::middleware::from_fn(keys::authenticate));
+    let app = Router::new()
+        .merge(api)
+        // Image URLs end up in `<img>` tags, which can't send a key
+        .route("/img/:file", get(blob::handler))
+        .nest("/admin", admin::router())
+        .route("/metrics", get(metrics::handler))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .route_layer(axum::middleware::from_fn(metrics::track_requests))
+        .layer(
+            CorsLayer::new()
+                .allow_methods(Any)
+                .allow_origin({
+                    let keys = keys.clone();
+                    AllowOrigin::predicate(move |origin, parts| keys.origin_allowed(origin, parts))
+                })
+                .allow_headers(Any),
+        )
+        .layer(CompressionLayer::new())
+        .layer(TraceLayer::new_for_http().make_span_with(keys::request_span))
+        .layer(Extension(prometheus))
+        .layer(Extension(state));
+
+    tracing::debug!("listening on {}", addr);
+    let (draining_tx, mut draining) = watch::channel(false);
+    let server = axum::Server::bind(&addr)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async move {
+            shutdown::signal().await;
+            info!("draining in-flight requests");
+            let _ = draining_tx.send(true);
+        });
+    tokio::select! {
+        res = server => res?,
+        _ = async {
+            let _ = draining.changed().await;
+            tokio::time::sleep(drain_timeout).await;
+        } => warn!("drain timeout reached, dropping remaining connections"),
+    }
+
+    // Dropping the server dropped its `State`, so the factory sees its
+    // channel close once background work holding a clone finishes
+    if tokio::time::timeout(Duration::from_secs(1), factory)
+        .await
+        .is_err()
+    {
+        warn!("token factory still has requesters, abandoning it");
+    }
+    match response_cache.save_snapshot().await {
+        Ok(0) => {}
+        Ok(saved) => info!("saved {saved} cache entries to snapshot"),
+        Err(e) => error!("failed to save cache snapshot: {:#}", e),
+    }
+    info!("shut down");
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct State {
+    client: Client,
+    token_channel: mpsc::Sender<TokenRequest>,
+    upstream: Arc<Upstream>,
+    response_cache: Arc<ResponseCache>,
+    blobs: Arc<BlobStore>,
+    keys: Arc<ApiKeys>,
+    tokens: Arc<PoolStatus>,
+    config: Arc<Config>,
+}
+
+impl State {
+    /// Everything requests share, with the token factory filling the pool in
+    /// the background until the last clone is dropped.
+    pub async fn new(config: Config) -> anyhow::Result<(Self, JoinHandle<()>)> {
+        let client = Client::builder()
+            .user_agent(&config.tokens.user_agent)
+            .build()?;
+        let upstream = Arc::new(Upstream::new(config.upstream.clone()));
+        let blobs = Arc::new(BlobStore::new(&config.blobs));
+        let response_cache = Arc::new(ResponseCache::new(config.cache.clone(), blobs.persistent()));
+        match response_cache.load_snapshot().await {
+            Ok(0) => {}
+            Ok(loaded) => info!("loaded {loaded} cache entries from snapshot"),
+            Err(e) => warn!("failed to load cache snapshot: {:#}", e),
+        }
+        let keys = Arc::new(ApiKeys::load(config.api_keys_file.as_deref())?);
+        {
+            let response_cache = response_cache.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(response_cache.sweep_interval());
+                loop {
+                    interval.tick().await;
+                    response_cache.sweep().await;
+                }
+            });
+        }
+        let (tx, mut rx) = mpsc::channel(config.tokens.buffer);
+        let tokens = Arc::new(PoolStatus::default());
+        let factory = {
+            let tokens = tokens.clone();
+            let token_config = config.tokens.clone();
+            tokio::spawn(async move {
+                let mut count = 0;
+                loop {
+                    let res = token_factory(&mut rx, tokens.clone(), &token_config).await;
+                    match res {
+                        Err(e) => {
+                            error!("{:#}", e);
+                        }
+                        Ok(()) => break,
+                    }
+                    error!("factory died! (reboot count: {count})");
+                    count += 1;
+                    ::metrics::increment_counter!("symbolab_token_factory_restarts_total");
+                }
+            })
+        };
+
+        let state = State {
+            client,
+            token_channel: tx,
+            upstream,
+            response_cache,
+            blobs,
+            keys,
+            tokens,
+            config: Arc::new(config),
+        };
+        Ok((state, factory))
+    }
+}
+
+pub async fn get_symbolab(
+    client: &Client,
+    token: &str,
+    payload: &Payload,
+) -> anyhow::Result<SymbolabResponse> {
+    let res = client
+        .get("https://www.symbolab.com/pub_api/steps")
+        .query(&[
+            ("query", payload.query.as_str()),
+            ("language", payload.language.as_str()),
+        ])
+        .query(&payload.options)
+        .bearer_auth(token)
+        // .header("sec-ch-ua", r#"" Not A;Brand";v="99", "Chromium";v="90", "Google Chrome";v="90""#)
+        // .header("referer", "https://www.symbolab.com/solver/step-by-step/x")
+        // .header("cache-control", "no-cache")
+        // .header("pragma", "no-cache")
+        // .header("sec-ch-ua-mobile", "?0")
+        // .header("sec-fetch-dest", "empty")
+        // .header("sec-fetch-mode", "cors")
+        // .header("sec-fetch-site", "same-origin")
+        .header("x-requested-with", "XMLHttpRequest")
+        .send()
+        .await;
+    let res = metrics::upstream_response("steps", res)?;
+    let symbolab: SymbolabResponse = res.json().await?;
+    Ok(symbolab)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, Hash, PartialEq)]
+pub struct Payload {
+    pub query: String,
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+    /// Preset the other colours are layered over, the deployment's defaults
+    /// when unset.
+    pub theme: Option<Theme>,
+    #[serde(default)]
+    pub colours: RoleColours,
+    #[serde(default)]
+    pub language: Language,
+    #[serde(default)]
+    pub options: RequestOptions,
+    #[serde(default)]
+    pub images: ImageMode,
+}
+
+impl Payload {
+    /// Validates the payload and puts it in canonical form, which is what
+    /// gets sent upstream and used as the cache key.
+    pub fn normalize(mut self) -> Result<Self> {
+        self.options.validate().map_err(Error::BadRequest)?;
+        self.query = normalize::query(&self.query).map_err(Error::BadRequest)?;
+        self.foreground = self
+            .foreground
+            .map(|fg| parse_colour("foreground", &fg))
+            .transpose()?;
+        self.background = self
+            .background
+            .map(|bg| parse_colour("background", &bg))
+            .transpose()?;
+        let colours = &mut self.colours;
+        for (field, colour) in [
+            ("colours.stepInput", &mut colours.step_input),
+            ("colours.result", &mut colours.result),
+            ("colours.highlight", &mut colours.highlight),
+            ("colours.title", &mut colours.title),
+        ] {
+            *colour = colour.take().map(|c| parse_colour(field, &c)).transpose()?;
+        }
+        Ok(self)
+    }
+
+    /// Role colours win over `foreground`, which wins over the theme.
+    fn palette(&self, config: &RenderConfig) -> Palette {
+        // Already validated by `normalize`
+        let pick = |colour: &Option<String>| colour.as_deref().and_then(|c| c.parse().ok());
+        let base = self
+            .theme
+            .map_or_else(|| Palette::from_config(config), Theme::palette);
+        let foreground = pick(&self.foreground);
+        let text = |role: &Option<String>, fallback| pick(role).or(foreground).unwrap_or(fallback);
+        Palette {
+            background: pick(&self.background).unwrap_or(base.background),
+            step_input: text(&self.colours.step_input, base.step_input),
+            result: text(&self.colours.result, base.result),
+            highlight: pick(&self.colours.highlight).unwrap_or(base.highlight),
+            title: text(&self.colours.title, base.title),
+        }
+    }
+
+    fn render_context(&self, state: &State) -> RenderContext {
+        RenderContext {
+            palette: self.palette(&state.config.render),
+            images: self.images,
+            blobs: state.blobs.clone(),
+            config: state.config.clone(),
+        }
+    }
+}
+
+/// Canonical `#rrggbbaa` form of a colour from a request.
+fn parse_colour(field: &str, colour: &str) -> Result<String> {
+    colour
+        .parse::<Colour>()
+        .map(Colour::to_hex)
+        .map_err(|e| Error::BadRequest(format!("{field}: {e}")))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SymbolabSvg {
+    canonical_notebook_query: Option<String>,
+    standard_query: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Solution {
+    step_input: Option<ImageSet>,
+    entire_result: Option<ImageSet>,
+    /// Set in XITS like the maths, or in a system font for scripts it lacks.
+    title: Option<ImageSet>,
+    title_text: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Data {
+    symbolab: SymbolabResponse,
+    cached: bool,
+    stale: bool,
+    canonical_notebook_query: Option<ImageSet>,
+    standard_query: Option<ImageSet>,
+    solutions: Vec<Solution>,
+    /// The problem and its answer read out, for the `alt` of whatever shows
+    /// the solution.
+    alt: Option<String>,
+    /// Cached by a document export, which only needs `symbolab`. Rendered
+    /// on the first hit that needs images, so never sent to clients.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    unrendered: bool,
+}
+
+impl Data {
+    fn unrendered(symbolab: SymbolabResponse) -> Self {
+        Self {
+            symbolab,
+            cached: false,
+            stale: false,
+            canonical_notebook_query: None,
+            standard_query: None,
+            solutions: Vec::new(),
+            alt: None,
+            unrendered: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageSet {
+    pub svg: Option<String>,
+    pub webp: Option<String>,
+    pub mathml: Option<String>,
+    /// Plain English, like "x squared plus one".
+    pub spoken: Option<String>,
+    /// AsciiMath, for plain text and copying.
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderContext {
+    palette: Palette,
+    images: ImageMode,
+    blobs: Arc<BlobStore>,
+    config: Arc<Config>,
+}
+
+impl RenderContext {
+    /// Renders with images inlined as data URIs, outside of any request.
+    pub fn inline(palette: Palette, config: Arc<Config>) -> Self {
+        Self {
+            palette,
+            images: ImageMode::Inline,
+            blobs: Arc::new(BlobStore::new(&config.blobs)),
+            config,
+        }
+    }
+
+    /// A data URI or blob URL for an encoded WebP, depending on the request.
+    /// Falls back to inlining when the image can't be stored.
+    async fn deliver(&self, encoded: Vec<u8>) -> String {
+        if self.images == ImageMode::Url {
+            match self.blobs.put("webp", &encoded).await {
+                Ok(url) => return url,
+                Err(e) => warn!("failed to store image, inlining it: {:#}", e),
+            }
+        }
+        inline_webp(&encoded)
+    }
+}
+
+fn inline_webp(encoded: &[u8]) -> String {
+    let mut b64 = base64::encode(encoded);
+    b64.insert_str(0, "data:image/webp;base64,");
+    b64
+}
+
+/// Renders `latex` with the WebP inlined, whatever `ctx` asks for, since
+/// storing it for a URL needs the runtime.
+pub fn get_image_set_sync(
+    latex: &str,
+    fg: Colour,
+    ctx: &RenderContext,
+) -> anyhow::Result<ImageSet> {
+    let (encoded, images) = render_image_set(latex, fg, ctx)?;
+    Ok(ImageSet {
+        webp: Some(inline_webp(&encoded)),
+        ..images
+    })
+}
+
+/// Everything but the WebP's data URI or URL, with the WebP itself.
+fn render_image_set(
+    latex: &str,
+    fg: Colour,
+    ctx: &RenderContext,
+) -> anyhow::Result<(Vec<u8>, ImageSet)> {
+    let palette = &ctx.palette;
+    let encoded = get_webp(
+        latex,
+        fg,
+        palette.highlight,
+        palette.background,
+        &ctx.config.render,
+    )?;
+    let tree = tex::parse_tree(latex)?;
+    let images = ImageSet {
+        svg: None,
+        webp: None,
+        mathml: Some(accessible::mathml(&tree)),
+        spoken: Some(accessible::spoken(&tree)),
+        text: Some(tex::ascii_math(&tree)),
+    };
+    Ok((encoded, images))
+}
+
+pub fn get_webp(
+    latex: &str,
+    fg: Colour,
+    highlight: Colour,
+    bg: Colour,
+    config: &RenderConfig,
+) -> anyhow::Result<Vec<u8>> {
+    let start = Instant::now();
+    let svg = tex::get_svg(latex, fg, highlight, config.layout_width)?;
+    ::metrics::histogram!("symbolab_render_duration_seconds", start.elapsed(), "format" => "svg");
+
+    encode_webp(&svg, bg, config)
+}
+
+fn encode_webp(svg: &str, bg: Colour, config: &RenderConfig) -> anyhow::Result<Vec<u8>> {
+    let start = Instant::now();
+    let pixmap = rasterise(svg, bg, config)?;
+    let encoder = webp::Encoder::from_rgba(pixmap.data(), pixmap.width(), pixmap.height());
+    let encoded = encoder.encode_lossless();
+    ::metrics::histogram!("symbolab_render_duration_seconds", start.elapsed(), "format" => "webp");
+    Ok(encoded.to_vec())
+}
+
+/// Draws `svg` on `bg` with `config.padding` around it.
+pub fn rasterise(
+    svg: &str,
+    bg: Colour,
+    config: &RenderConfig,
+) -> anyhow::Result<tiny_skia::Pixmap> {
+    let rtree = fallback::with_fonts(|opt| usvg::Tree::from_data(svg.as_bytes(), opt))?;
+    let pixmap_size = rtree.svg_node().size.to_screen_size();
+    let mut pixmap = tiny_skia::Pixmap::new(
+        pixmap_size.width() + config.padding * 2,
+        pixmap_size.height() + config.padding * 2,
+    )
+    .context("failed to create pixmap")?;
+    pixmap.fill(Color::from_rgba8(bg.r, bg.g, bg.b, bg.a));
+
+    resvg::render(
+        &rtree,
+        usvg::FitTo::Size(pixmap_size.width(), pixmap_size.height()),
+        tiny_skia::Transform::from_translate(config.padding as f32, config.padding as f32),
+        pixmap.as_mut(),
+    )
+    .context("failed to render")?;
+    Ok(pixmap)
+}
+
+async fn get_image_set(
+    latex: Option<&str>,
+    fg: Colour,
+    ctx: &RenderContext,
+) -> anyhow::Result<Option<ImageSet>> {
+    Ok(if let Some(latex) = latex {
+        let cleaned = clean_latex(latex);
+        let (encoded, images) = render_image_set(&cleaned, fg, ctx)?;
+        Some(ImageSet {
+            webp: Some(ctx.deliver(encoded).await),
+            ..images
+        })
+    } else {
+        None
+    })
+}
+
+/// Step titles are prose in the request's language, which XITS often has no
+/// glyphs for. Those are set in a system font instead, without MathML.
+async fn get_title_image_set(
+    title: Option<&str>,
+    ctx: &RenderContext,
+) -> anyhow::Result<Option<ImageSet>> {
+    let Some(title) = title else {
+        return Ok(None);
+    };
+    if tex::is_covered(&clean_latex(title))? {
+        return get_image_set(Some(title), ctx.palette.title, ctx).await;
+    }
+    let text = tex::title_text(title);
+    let render = &ctx.config.render;
+    let svg = fallback::text_svg(&text, ctx.palette.title)?;
+    let encoded = encode_webp(&svg, ctx.palette.background, render)?;
+    Ok(Some(ImageSet {
+        svg: None,
+        webp: Some(ctx.deliver(encoded).await),
+        mathml: None,
+        spoken: Some(text.clone()),
+        text: Some(text),
+    }))
+}
+
+/// Swaps the Unicode symbols Symbolab sprinkles into its LaTeX for the
+/// commands ReX understands.
+pub fn clean_latex(latex: &str) -> String {
+    latex
+        .replace('…', r#"\ldots "#)
+        .replace('π', r#"\pi "#)
+        .replace('∞', r#"\infty "#)
+        .replace('∫', r#"\int "#)
+        .replace('∑', r#"\sum "#)
+        .replace('√', r#"\sqrt "#)
+        .replace('∂', r#"\partial "#)
+        .replace('∇', r#"\nabla "#)
+        .replace('∀', r#"\forall "#)
+        .replace('∃', r#"\exists "#)
+        .replace('∈', r#"\in "#)
+        .replace('∉', r#"\notin "#)
+        .replace('∋', r#"\ni "#)
+        .replace('∌', r#"\notni "#)
+        .replace('∏', r#"\prod "#)
+        .replace('∐', r#"\coprod "#)
+        .replace('∓', r#"\mp "#)
+        .replace('∔', r#"\dotplus "#)
+        .replace('∘', r#"\circ "#)
+        .replace('∝', r#"\propto "#)
+}
+
+async fn handler(
+    Json(payload): Json<Payload>,
+    Extension(state): Extension<State>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<Json<Data>> {
+    let client = client_key(&headers, addr, &state.config.upstream);
+    let data = solve_cached(&state, Caller::Client(&client), payload).await?;
+    Ok(Json(data))
+}
+
+async fn solve_cached(state: &State, caller: Caller<'_>, payload: Payload) -> Result<Data> {
+    let payload = payload.normalize()?;
+    let symbolab = match lookup_or_fetch(state, caller, &payload).await? {
+        Fetched::Cached(data) if data.unrendered => data.symbolab,
+        Fetched::Cached(data) => return Ok(*data),
+        Fetched::Upstream(symbolab) => *symbolab,
+    };
+    let data = render_data(state, &payload, symbolab).await?;
+    let response_cache = state.response_cache.clone();
+    let cached = data.clone();
+    tokio::spawn(async move { response_cache.insert(payload, cached).await });
+    Ok(data)
+}
+
+/// A solution from the cache, or Symbolab's answer still to be rendered.
+/// Cached solutions may also need rendering, see [`Data::unrendered`].
+enum Fetched {
+    Cached(Box<Data>),
+    Upstream(Box<SymbolabResponse>),
+}
+
+/// Serves fresh and stale cache entries, revalidating stale ones in the
+/// background, and asks Symbolab otherwise. Expired entries are still served
+/// when Symbolab can't be reached.
+async fn lookup_or_fetch(state: &State, caller: Caller<'_>, payload: &Payload) -> Result<Fetched> {
+    let fallback = match state.response_cache.lookup(payload).await {
+        Lookup::Fresh(data) => return Ok(Fetched::Cached(Box::new(data))),
+        Lookup::Stale { data, revalidate } => {
+            if revalidate {
+                tokio::spawn(revalidate_cached(state.clone(), payload.clone()));
+            }
+            return Ok(Fetched::Cached(Box::new(data)));
+        }
+        Lookup::Expired(data) => Some(data),
+        Lookup::Miss => None,
+    };
+    tracing::info!("cache miss");
+    match fetch_symbolab(state, caller, payload).await {
+        Ok(symbolab) => Ok(Fetched::Upstream(Box::new(symbolab))),
+        Err(e) => match fallback {
+            Some(data) => {
+                warn!("serving stale entry after upstream error");
+                if let Error::Internal(e) = e {
+                    error!("{:#}", e);
+                }
+                Ok(Fetched::Cached(Box::new(data)))
+            }
+            None => Err(e),
+        },
+    }
+}
+
+async fn revalidate_cached(state: State, payload: Payload) {
+    tracing::info!("revalidating stale entry");
+    match solve(&state, Caller::Internal, &payload).await {
+        Ok(data) => state.response_cache.insert(payload, data).await,
+        Err(e) => {
+            match e {
+                Error::Internal(e) => warn!("failed to revalidate stale entry: {:#}", e),
+                _ => warn!("upstream unavailable, couldn't revalidate stale entry"),
+            }
+            state.response_cache.revalidation_failed(&payload).await;
+        }
+    }
+}
+
+async fn fetch_symbolab(
+    state: &State,
+    caller: Caller<'_>,
+    payload: &Payload,
+) -> Result<SymbolabResponse> {
+    state
+        .upstream
+        .call(caller, || async {
+            let token =
+                get_cached_token(&state.token_channel, state.config.tokens.wait_timeout).await?;
+            Ok(get_symbolab(&state.client, &token, payload).await?)
+        })
+        .await
+}
+
+fn render_queries(
+    symbolab: &SymbolabResponse,
+    ctx: &RenderContext,
+) -> JoinHandle<anyhow::Result<(Option<ImageSet>, Option<ImageSet>)>> {
+    let canonical_notebook_query = symbolab.canonical_notebook_query.clone();
+    let standard_query = symbolab.standard_query.clone();
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        tokio::try_join!(
+            get_image_set(
+                canonical_notebook_query.as_deref(),
+                ctx.palette.step_input,
+                &ctx
+            ),
+            get_image_set(standard_query.as_deref(), ctx.palette.step_input, &ctx)
+        )
+    })
+}
+
+fn render_solution(
+    solution: &SolutionElement,
+    ctx: &RenderContext,
+) -> JoinHandle<anyhow::Result<Solution>> {
+    let step_input = solution.step_input.clone();
+    let entire_result = solution.entire_result.clone();
+    let title = solution
+        .title
+        .as_ref()
+        .and_then(|title| title.text.as_ref())
+        .and_then(|text| text.created_text.clone());
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let (step_input, entire_result, title_image) = tokio::join!(
+            get_image_set(step_input.as_deref(), ctx.palette.step_input, &ctx),
+            get_image_set(entire_result.as_deref(), ctx.palette.result, &ctx),
+            get_title_image_set(title.as_deref(), &ctx)
+        );
+        // Titles are mostly prose, which ReX doesn't always handle
+        let title_image = title_image.unwrap_or_else(|e| {
+            warn!("failed to render title: {:#}", e);
+            None
+        });
+        Ok(Solution {
+            step_input: step_input?,
+            entire_result: entire_result?,
+            title: title_image,
+            title_text: title.as_deref().map(tex::title_text),
+        })
+    })
+}
+
+async fn solve(state: &State, caller: Caller<'_>, payload: &Payload) -> Result<Data> {
+    let symbolab = fetch_symbolab(state, caller, payload).await?;
+    render_data(state, payload, symbolab).await
+}
+
+async fn render_data(state: &State, payload: &Payload, symbolab: SymbolabResponse) -> Result<Data> {
+    let ctx = payload.render_context(state);
+    let queries_handle = render_queries(&symbolab, &ctx);
+    let handles = symbolab
+        .solutions
+        .iter()
+        .flatten()
+        .map(|solution| render_solution(solution, &ctx))
+        .collect::<Vec<_>>();
+    let mut solutions = Vec::with_capacity(handles.len());
+    for handle in handles {
+        let solution = handle.await.context("failed to fetch solution")??;
+        solutions.push(solution);
+    }
+
+    let (canonical_notebook_query, standard_query) =
+        queries_handle.await.context("failed to fetch queries")??;
+
+    Ok(Data {
+        symbolab,
+        canonical_notebook_query,
+        alt: alt_text(standard_query.as_ref(), &solutions),
+        standard_query,
+        solutions,
+        cached: false,
+        stale: false,
+        unrendered: false,
+    })
+}
+
+fn alt_text(query: Option<&ImageSet>, solutions: &[Solution]) -> Option<String> {
+    let query = query?.spoken.as_deref()?;
+    let results = solutions
+        .iter()
+        .filter_map(|solution| solution.entire_result.as_ref()?.spoken.as_deref())
+        .collect::<Vec<_>>();
+    Some(if results.is_empty() {
+        query.to_owned()
+    } else {
+        format!("{query}. Solution: {}", results.join("; "))
+    })
+}
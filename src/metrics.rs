@@ -0,0 +1,94 @@
+use std::time::Instant;
+
+use axum::{
+    extract::MatchedPath,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use metrics::{absolute_counter, gauge, histogram, increment_counter};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+
+use crate::State;
+
+/// Seconds, covering everything from cache hits to slow upstream solves.
+const BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+pub fn install() -> anyhow::Result<PrometheusHandle> {
+    Ok(PrometheusBuilder::new()
+        .set_buckets(BUCKETS)?
+        .install_recorder()?)
+}
+
+pub async fn handler(
+    Extension(state): Extension<State>,
+    Extension(handle): Extension<PrometheusHandle>,
+) -> impl IntoResponse {
+    // Things that are cheaper to read at scrape time than to track as they change
+    let current = state.upstream.breaker_state().name();
+    for name in ["closed", "open", "half_open"] {
+        let value = if current == name { 1.0 } else { 0.0 };
+        gauge!("symbolab_upstream_circuit_state", value, "state" => name);
+    }
+
+    let stats = state.response_cache.stats().await;
+    gauge!("symbolab_cache_entries", stats.entries as f64);
+    gauge!("symbolab_cache_size_bytes", stats.size_bytes as f64);
+    absolute_counter!("symbolab_cache_hits_total", stats.hits);
+    absolute_counter!("symbolab_cache_misses_total", stats.misses);
+    absolute_counter!("symbolab_cache_evictions_total", stats.evictions);
+    let lookups = stats.hits + stats.misses;
+    if lookups > 0 {
+        gauge!(
+            "symbolab_cache_hit_ratio",
+            stats.hits as f64 / lookups as f64
+        );
+    }
+
+    handle.render()
+}
+
+/// Counts requests and their latency by route, method and status.
+pub async fn track_requests<B>(req: Request<B>, next: Next<B>) -> Response {
+    let start = Instant::now();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched".to_owned(), |path| path.as_str().to_owned());
+    let method = req.method().to_string();
+
+    let res = next.run(req).await;
+
+    let labels = [
+        ("route", route),
+        ("method", method),
+        ("status", res.status().as_u16().to_string()),
+    ];
+    increment_counter!("symbolab_http_requests_total", &labels);
+    histogram!(
+        "symbolab_http_request_duration_seconds",
+        start.elapsed(),
+        &labels
+    );
+    res
+}
+
+/// Counts failed upstream calls by endpoint and status code, `transport` for
+/// ones that never got an answer, and turns error statuses into errors.
+pub fn upstream_response(
+    endpoint: &'static str,
+    res: reqwest::Result<reqwest::Response>,
+) -> reqwest::Result<reqwest::Response> {
+    let code = match &res {
+        Ok(res) if res.status().is_success() => None,
+        Ok(res) => Some(res.status().as_u16().to_string()),
+        Err(_) => Some("transport".to_owned()),
+    };
+    if let Some(code) = code {
+        increment_counter!("symbolab_upstream_errors_total", "endpoint" => endpoint, "code" => code);
+    }
+    res?.error_for_status()
+}
@@ -0,0 +1,114 @@
+/// Longest query accepted, in bytes.
+pub const MAX_QUERY_LENGTH: usize = 1000;
+
+/// Canonical form of a query, so equivalent spellings share a cache entry and
+/// send the same request upstream.
+///
+/// Whitespace is dropped next to operators and brackets and collapsed to one
+/// space elsewhere, since `1 2` isn't `12` and `\sin x` isn't `\sinx`.
+/// Single-character scripts lose their braces, so `x^{2}+1` becomes `x^2+1`,
+/// unless that would merge them with what follows.
+pub fn query(query: &str) -> Result<String, String> {
+    if query.len() > MAX_QUERY_LENGTH {
+        return Err(format!("query is longer than {MAX_QUERY_LENGTH} bytes"));
+    }
+    if let Some(c) = query.chars().find(|c| c.is_control() && !c.is_whitespace()) {
+        return Err(format!(
+            "query contains the control character U+{:04X}",
+            c as u32
+        ));
+    }
+
+    let mut out = String::with_capacity(query.len());
+    let mut pending_space = false;
+    for c in query.chars() {
+        if c.is_whitespace() {
+            pending_space = !out.is_empty();
+            continue;
+        }
+        if pending_space {
+            let keep = match out.chars().last() {
+                Some('\\') => ends_with_control_space(&out),
+                Some(prev) => prev.is_alphanumeric() && c.is_alphanumeric(),
+                None => false,
+            };
+            if keep {
+                out.push(' ');
+            }
+            pending_space = false;
+        }
+        out.push(c);
+    }
+    if pending_space && ends_with_control_space(&out) {
+        out.push(' ');
+    }
+
+    let out = unbrace_scripts(&out);
+    if out.is_empty() {
+        return Err("query is empty".to_owned());
+    }
+    Ok(out)
+}
+
+/// Whether `s` ends in a backslash that isn't itself escaped, making a
+/// following space significant.
+fn ends_with_control_space(s: &str) -> bool {
+    s.chars().rev().take_while(|&c| c == '\\').count() % 2 == 1
+}
+
+/// `^{2}` -> `^2` and `_{n}` -> `_n` when the next character can't be read as
+/// part of the script.
+fn unbrace_scripts(s: &str) -> String {
+    let chars = s.chars().collect::<Vec<_>>();
+    let mut out = String::with_capacity(s.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        out.push(c);
+        let is_script = matches!(c, '^' | '_') && (i == 0 || chars[i - 1] != '\\');
+        if is_script
+            && chars.get(i + 1) == Some(&'{')
+            && chars.get(i + 2).is_some_and(|c| c.is_alphanumeric())
+            && chars.get(i + 3) == Some(&'}')
+            && !chars.get(i + 4).is_some_and(|c| c.is_alphanumeric())
+        {
+            out.push(chars[i + 2]);
+            i += 4;
+        } else {
+            i += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_queries() {
+        let cases = [
+            ("x^{2}+1", "x^2+1"),
+            (" x^2 + 1 ", "x^2+1"),
+            ("x^{2}y", "x^{2}y"),
+            ("x_{n} + 1", "x_n+1"),
+            ("1 2", "1 2"),
+            (r"\sin  x", r"\sin x"),
+            (r"\ ", r"\ "),
+            (r"a\ b", r"a\ b"),
+            (r"\\", r"\\"),
+            (r"a\\ b", r"a\\b"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(query(input).as_deref(), Ok(expected), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_queries() {
+        assert!(query("").is_err());
+        assert!(query("   ").is_err());
+        assert!(query("x\u{7}").is_err());
+        assert!(query(&"x".repeat(MAX_QUERY_LENGTH + 1)).is_err());
+    }
+}
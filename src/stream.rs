@@ -0,0 +1,226 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use anyhow::Context;
+use axum::{
+    extract::ConnectInfo,
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+    Extension, Json,
+};
+use futures::{future::BoxFuture, stream::FuturesUnordered, FutureExt, Stream, StreamExt};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::error;
+
+use crate::{
+    alt_text,
+    error::Result,
+    lookup_or_fetch, render_queries, render_solution,
+    symbolab::SymbolabResponse,
+    upstream::{client_key, Caller},
+    Data, Fetched, ImageSet, Payload, Solution, State,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Metadata<'a> {
+    symbolab: &'a SymbolabResponse,
+    cached: bool,
+    stale: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Queries<'a> {
+    canonical_notebook_query: &'a Option<ImageSet>,
+    standard_query: &'a Option<ImageSet>,
+}
+
+#[derive(Serialize)]
+struct IndexedSolution<'a> {
+    index: usize,
+    solution: &'a Solution,
+}
+
+#[derive(Serialize)]
+struct Done {
+    solutions: usize,
+}
+
+#[derive(Serialize)]
+struct StreamError {
+    message: &'static str,
+}
+
+enum Rendered {
+    Queries(anyhow::Result<(Option<ImageSet>, Option<ImageSet>)>),
+    Solution(usize, anyhow::Result<Solution>),
+}
+
+/// Same as `POST /` but over Server-Sent Events: a `metadata` event with the
+/// Symbolab response, then `queries` and one `solution` event per solution as
+/// their renders finish, then `done`. A failed render ends the stream with an
+/// `error` event instead.
+pub async fn handler(
+    Json(payload): Json<Payload>,
+    Extension(state): Extension<State>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = core::result::Result<Event, Infallible>>>> {
+    let payload = payload.normalize()?;
+    let (tx, rx) = mpsc::channel(16);
+    let client = client_key(&headers, addr, &state.config.upstream);
+    match lookup_or_fetch(&state, Caller::Client(&client), &payload).await? {
+        Fetched::Cached(data) if data.unrendered => {
+            tokio::spawn(render(state, payload, data.symbolab, tx));
+        }
+        Fetched::Cached(data) => {
+            tokio::spawn(send_cached(tx, *data));
+        }
+        Fetched::Upstream(symbolab) => {
+            tokio::spawn(render(state, payload, *symbolab, tx));
+        }
+    }
+    Ok(sse(rx))
+}
+
+fn sse(
+    rx: mpsc::Receiver<Event>,
+) -> Sse<impl Stream<Item = core::result::Result<Event, Infallible>>> {
+    let events = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|event| (Ok(event), rx))
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+async fn send<T: Serialize>(tx: &mpsc::Sender<Event>, name: &str, data: &T) {
+    match Event::default().event(name).json_data(data) {
+        // The client going away doesn't stop the render, the result still
+        // ends up in the cache
+        Ok(event) => {
+            let _ = tx.send(event).await;
+        }
+        Err(e) => error!("failed to serialize `{}` event: {}", name, e),
+    }
+}
+
+async fn send_cached(tx: mpsc::Sender<Event>, data: Data) {
+    let metadata = Metadata {
+        symbolab: &data.symbolab,
+        cached: data.cached,
+        stale: data.stale,
+    };
+    send(&tx, "metadata", &metadata).await;
+    let queries = Queries {
+        canonical_notebook_query: &data.canonical_notebook_query,
+        standard_query: &data.standard_query,
+    };
+    send(&tx, "queries", &queries).await;
+    for (index, solution) in data.solutions.iter().enumerate() {
+        send(&tx, "solution", &IndexedSolution { index, solution }).await;
+    }
+    let done = Done {
+        solutions: data.solutions.len(),
+    };
+    send(&tx, "done", &done).await;
+}
+
+async fn render(
+    state: State,
+    payload: Payload,
+    symbolab: SymbolabResponse,
+    tx: mpsc::Sender<Event>,
+) {
+    let metadata = Metadata {
+        symbolab: &symbolab,
+        cached: false,
+        stale: false,
+    };
+    send(&tx, "metadata", &metadata).await;
+
+    let ctx = payload.render_context(&state);
+    let mut pending: FuturesUnordered<BoxFuture<'static, Rendered>> = FuturesUnordered::new();
+    let queries_handle = render_queries(&symbolab, &ctx);
+    pending.push(
+        async move {
+            Rendered::Queries(
+                queries_handle
+                    .await
+                    .context("failed to fetch queries")
+                    .and_then(|res| res),
+            )
+        }
+        .boxed(),
+    );
+    for (index, solution) in symbolab.solutions.iter().flatten().enumerate() {
+        let handle = render_solution(solution, &ctx);
+        pending.push(
+            async move {
+                Rendered::Solution(
+                    index,
+                    handle
+                        .await
+                        .context("failed to fetch solution")
+                        .and_then(|res| res),
+                )
+            }
+            .boxed(),
+        );
+    }
+
+    let mut queries = None;
+    let mut solutions = vec![None; pending.len() - 1];
+    while let Some(rendered) = pending.next().await {
+        let res = match rendered {
+            Rendered::Queries(Ok((canonical_notebook_query, standard_query))) => {
+                let event = Queries {
+                    canonical_notebook_query: &canonical_notebook_query,
+                    standard_query: &standard_query,
+                };
+                send(&tx, "queries", &event).await;
+                queries = Some((canonical_notebook_query, standard_query));
+                Ok(())
+            }
+            Rendered::Solution(index, Ok(solution)) => {
+                send(
+                    &tx,
+                    "solution",
+                    &IndexedSolution {
+                        index,
+                        solution: &solution,
+                    },
+                )
+                .await;
+                solutions[index] = Some(solution);
+                Ok(())
+            }
+            Rendered::Queries(Err(e)) | Rendered::Solution(_, Err(e)) => Err(e),
+        };
+        if let Err(e) = res {
+            error!("{:#}", e);
+            let message = StreamError {
+                message: "Something went wrong",
+            };
+            send(&tx, "error", &message).await;
+            return;
+        }
+    }
+
+    let (canonical_notebook_query, standard_query) = queries.unwrap_or_default();
+    let solutions = solutions.into_iter().flatten().collect::<Vec<_>>();
+    let done = Done {
+        solutions: solutions.len(),
+    };
+    let data = Data {
+        symbolab,
+        canonical_notebook_query,
+        alt: alt_text(standard_query.as_ref(), &solutions),
+        standard_query,
+        solutions,
+        cached: false,
+        stale: false,
+        unrendered: false,
+    };
+    state.response_cache.insert(payload, data).await;
+    send(&tx, "done", &done).await;
+}
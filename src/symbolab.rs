@@ -133,4 +133,93 @@ pub struct Step {
     pub is_show_solution_after_step: Option<bool>,
     pub title: Option<Title>,
     pub general_rule: Option<Title>,
+    /// How this step was worked out, when Symbolab breaks it down further.
+    pub steps: Option<Vec<Step>>,
+}
+
+/// Languages Symbolab can write step explanations in.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    En,
+    Es,
+    Fr,
+    De,
+    Pt,
+    It,
+    Ru,
+    Zh,
+    Ja,
+    Ko,
+    Vi,
+    He,
+    Ar,
+    Tr,
+}
+
+impl Language {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Language::En => "en",
+            Language::Es => "es",
+            Language::Fr => "fr",
+            Language::De => "de",
+            Language::Pt => "pt",
+            Language::It => "it",
+            Language::Ru => "ru",
+            Language::Zh => "zh",
+            Language::Ja => "ja",
+            Language::Ko => "ko",
+            Language::Vi => "vi",
+            Language::He => "he",
+            Language::Ar => "ar",
+            Language::Tr => "tr",
+        }
+    }
+}
+
+/// Knobs passed through to the steps API.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RequestOptions {
+    pub subscribed: bool,
+    pub plot_request: PlotRequest,
+    pub page: Page,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub solution_level: Option<String>,
+}
+
+impl RequestOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(level) = &self.solution_level {
+            if level.is_empty()
+                || level.len() > 32
+                || !level.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                return Err(format!("invalid solutionLevel `{level}`"));
+            }
+        }
+        if self.page == Page::Graphing && self.plot_request == PlotRequest::NoPlot {
+            return Err("the graphing page always returns a plot".to_owned());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Eq, Hash, PartialEq)]
+pub enum PlotRequest {
+    #[default]
+    PlotOptional,
+    PlotRequired,
+    NoPlot,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Page {
+    #[default]
+    StepByStep,
+    #[serde(rename = "graphing-calculator")]
+    Graphing,
 }
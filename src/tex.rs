@@ -1,49 +1,92 @@
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 
+use font::Font;
+use once_cell::sync::Lazy;
 use pathfinder_export::{Export, FileFormat};
 use pathfinder_geometry::{rect::RectF, vector::vec2f};
 use pathfinder_renderer::scene::Scene;
 use rex::{
     font::FontContext,
     layout::{engine, Grid, Layout, LayoutSettings, Style},
-    parser::{nodes::Color, parse, ParseNode},
+    parser::{
+        nodes::{AtomType, Color},
+        parse, ParseNode,
+    },
     render::{Renderer, SceneWrapper},
     RGBA,
 };
+use tracing::warn;
 
-pub fn get_svg(input: &str, color: &str) -> anyhow::Result<String> {
-    let parsed = parse(&input).ok().context("failed to parse TeX input")?;
-    let rgba = RGBA::from_name(color)
-        .or_else(|| -> Option<_> {
-            let hex = color.trim_start_matches('#');
-            let r: u8 = u8::from_str_radix(hex.get(0..2)?, 16).ok()?;
-            let g: u8 = u8::from_str_radix(hex.get(2..4)?, 16).ok()?;
-            let b: u8 = u8::from_str_radix(hex.get(4..6)?, 16).ok()?;
-            let a: u8 = u8::from_str_radix(hex.get(6..8).unwrap_or("ff"), 16).ok()?;
-            Some(RGBA(r, g, b, a))
-        })
-        .unwrap_or(RGBA(0, 0, 0, 255));
-    let styled = ParseNode::Color(Color {
-        color: rgba,
-        inner: parsed,
-    });
-    let font = font::parse(include_bytes!("../rex-xits.otf"))
+use crate::colour::Colour;
+
+static FONT: Lazy<Result<Box<font::OpenTypeFont>, &'static str>> = Lazy::new(|| {
+    font::parse(include_bytes!("../rex-xits.otf"))
         .ok()
-        .context("failed to parse font")?
+        .ok_or("failed to parse font")?
         .downcast_box()
         .ok()
-        .context("failed to downcast font")?;
+        .ok_or("failed to downcast font")
+});
 
-    let mut grid = Grid::new();
+/// XITS, parsed on first use.
+pub fn load_font() -> anyhow::Result<&'static font::OpenTypeFont> {
+    FONT.as_deref().map_err(|e| anyhow!(*e))
+}
 
-    let ctx = FontContext::new(&font);
-    let layout_settings = LayoutSettings::new(&ctx, 500.0, Style::Display);
-    let node = engine::layout(&[styled], layout_settings)
-        .ok()
-        .context("failed to generate layout")?
-        .as_node();
+/// Typesets `input` in `color`, with the terms Symbolab highlights in
+/// `highlight`.
+pub fn get_svg(
+    input: &str,
+    color: Colour,
+    highlight: Colour,
+    layout_width: f64,
+) -> anyhow::Result<String> {
+    let styled = styled(input, color, highlight)?;
+    let (scene, _) = draw(vec![styled], layout_width)?;
+
+    let mut buf = Vec::new();
+    scene.export(&mut buf, FileFormat::SVG)?;
+    let svg = String::from_utf8(buf)?;
+    Ok(svg)
+}
+
+/// Parses `input` and colours it for [`draw`].
+pub fn styled(input: &str, color: Colour, highlight: Colour) -> anyhow::Result<ParseNode> {
+    let parsed = match parse(&highlight_markup(input, Some(highlight))) {
+        Ok(parsed) => parsed,
+        // ReX doesn't know every colour Symbolab uses, plain is better than nothing
+        Err(e) => {
+            warn!(
+                input,
+                "rendering without highlights after parse error: {e:?}"
+            );
+            parse(&highlight_markup(input, None))
+                .ok()
+                .context("failed to parse TeX input")?
+        }
+    };
+    Ok(ParseNode::Color(Color {
+        color: RGBA(color.r, color.g, color.b, color.a),
+        inner: parsed,
+    }))
+}
+
+/// Lays `rows` out one under another, returning the scene and its bounds as
+/// `(x0, y0, x1, y1)`.
+pub fn draw(rows: Vec<ParseNode>, layout_width: f64) -> anyhow::Result<(Scene, [f64; 4])> {
+    let font = load_font()?;
 
-    grid.insert(0, 0, node);
+    let mut grid = Grid::new();
+
+    let ctx = FontContext::new(font);
+    for (i, row) in rows.into_iter().enumerate() {
+        let layout_settings = LayoutSettings::new(&ctx, layout_width, Style::Display);
+        let node = engine::layout(&[row], layout_settings)
+            .ok()
+            .context("failed to generate layout")?
+            .as_node();
+        grid.insert(i, 0, node);
+    }
     let mut layout = Layout::new();
     layout.add_node(grid.build());
 
@@ -56,9 +99,315 @@ pub fn get_svg(input: &str, color: &str) -> anyhow::Result<String> {
     ));
     let mut backend = SceneWrapper::new(&mut scene);
     renderer.render(&layout, &mut backend);
+    Ok((scene, [x0, y0, x1, y1]))
+}
 
-    let mut buf = Vec::new();
-    scene.export(&mut buf, FileFormat::SVG)?;
-    let svg = String::from_utf8(buf)?;
-    Ok(svg)
+/// The parse tree of `input` without colours, for output that isn't an
+/// image.
+pub fn parse_tree(input: &str) -> anyhow::Result<Vec<ParseNode>> {
+    parse(&highlight_markup(input, None))
+        .ok()
+        .context("failed to parse TeX input")
+}
+
+/// AsciiMath for the parse tree, like `x^2 + 1` or `(a+1)/b`, readable as
+/// plain text where images and MathML aren't an option.
+pub fn ascii_math(nodes: &[ParseNode]) -> String {
+    let mut out = String::new();
+    ascii_row(nodes, &mut out);
+    out.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// AsciiMath spellings of symbols that differ from the symbol itself.
+const ASCII_SYMBOLS: &[(char, &str)] = &[
+    ('−', "-"),
+    ('×', "*"),
+    ('⋅', "*"),
+    ('·', "*"),
+    ('∗', "*"),
+    ('÷', "-:"),
+    ('±', "+-"),
+    ('≤', "<="),
+    ('≥', ">="),
+    ('≠', "!="),
+    ('≈', "~~"),
+    ('→', "->"),
+    ('⇒', "=>"),
+    ('∞', "oo"),
+    ('∈', "in"),
+    ('∉', "!in"),
+    ('∪', "uu"),
+    ('∩', "nn"),
+    ('∫', "int"),
+    ('∑', "sum"),
+    ('∏', "prod"),
+    ('∂', "del"),
+    ('∇', "grad"),
+    ('°', "^@"),
+    ('α', "alpha"),
+    ('β', "beta"),
+    ('γ', "gamma"),
+    ('δ', "delta"),
+    ('ε', "epsilon"),
+    ('θ', "theta"),
+    ('λ', "lambda"),
+    ('μ', "mu"),
+    ('π', "pi"),
+    ('ρ', "rho"),
+    ('σ', "sigma"),
+    ('τ', "tau"),
+    ('φ', "phi"),
+    ('ω', "omega"),
+    ('Δ', "Delta"),
+    ('Σ', "Sigma"),
+    ('Ω', "Omega"),
+];
+
+fn ascii_row(nodes: &[ParseNode], out: &mut String) {
+    for node in nodes {
+        ascii_node(node, out);
+    }
+}
+
+fn ascii_node(node: &ParseNode, out: &mut String) {
+    match node {
+        ParseNode::Symbol(symbol) => {
+            let c = plain(symbol.codepoint);
+            let text = ASCII_SYMBOLS
+                .iter()
+                .find(|&&(s, _)| s == c)
+                .map_or_else(|| c.to_string(), |(_, text)| (*text).to_owned());
+            match symbol.atom_type {
+                AtomType::Binary | AtomType::Relation => out.push_str(&format!(" {text} ")),
+                AtomType::Punctuation => out.push_str(&format!("{text} ")),
+                // Named symbols need a space before whatever follows
+                _ if text.len() > 1 && text.chars().all(char::is_alphabetic) => {
+                    out.push_str(&format!("{text} "))
+                }
+                _ => out.push_str(&text),
+            }
+        }
+        ParseNode::Scripts(scripts) => {
+            if let Some(base) = &scripts.base {
+                ascii_node(base, out);
+            }
+            // Keep `sin^2 x` together
+            let spaced = out.ends_with(' ');
+            if spaced {
+                out.pop();
+            }
+            if let Some(subscript) = &scripts.subscript {
+                out.push('_');
+                ascii_operand(subscript, out);
+            }
+            if let Some(superscript) = &scripts.superscript {
+                out.push('^');
+                ascii_operand(superscript, out);
+            }
+            if spaced {
+                out.push(' ');
+            }
+        }
+        ParseNode::GenFraction(fraction) => {
+            ascii_operand(&fraction.numerator, out);
+            out.push('/');
+            ascii_operand(&fraction.denominator, out);
+        }
+        ParseNode::Radical(radical) => {
+            out.push_str("sqrt");
+            ascii_operand(&radical.inner, out);
+        }
+        ParseNode::Delimited(delimited) => {
+            out.push(plain(delimited.left.codepoint));
+            ascii_row(&delimited.inner, out);
+            out.push(plain(delimited.right.codepoint));
+        }
+        ParseNode::AtomChange(change) if matches!(change.at, AtomType::Operator(_)) => {
+            out.push(' ');
+            ascii_row(&change.inner, out);
+            out.push(' ');
+        }
+        ParseNode::Accent(accent) => ascii_row(&accent.nucleus, out),
+        ParseNode::Stack(stack) => {
+            for (i, line) in stack.lines.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                ascii_row(line, out);
+            }
+        }
+        ParseNode::Color(color) => ascii_row(&color.inner, out),
+        ParseNode::AtomChange(change) => ascii_row(&change.inner, out),
+        ParseNode::Group(inner) => ascii_row(inner, out),
+        // Spacing and style changes
+        _ => {}
+    }
+}
+
+/// A script, numerator or root, in brackets unless it's a single token.
+fn ascii_operand(nodes: &[ParseNode], out: &mut String) {
+    let mut text = String::new();
+    ascii_row(nodes, &mut text);
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().all(char::is_alphanumeric) {
+        out.push_str(&text);
+    } else {
+        out.push_str(&format!("({text})"));
+    }
+}
+
+/// ASCII for the italic and bold letters ReX may have swapped in.
+pub fn plain(c: char) -> char {
+    match c as u32 {
+        code @ 0x1D400..=0x1D6A3 => {
+            let letter = (code - 0x1D400) % 52;
+            let base = if letter < 26 { b'A' } else { b'a' };
+            (base + (letter % 26) as u8) as char
+        }
+        _ => c,
+    }
+}
+
+/// `input` without Symbolab's highlight markup, for exporting as LaTeX.
+pub fn strip_markup(input: &str) -> String {
+    highlight_markup(input, None)
+}
+
+/// Rewrites Symbolab's `\class{highlight}{..}` and `\textcolor{..}{..}`
+/// markup into `\color`, which ReX understands, or drops it when `highlight`
+/// is `None`. Other classes only matter to Symbolab's stylesheet and are
+/// dropped either way.
+fn highlight_markup(input: &str, highlight: Option<Colour>) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('\\') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let command = if rest.starts_with(r"\class{") {
+            r"\class"
+        } else if rest.starts_with(r"\textcolor{") {
+            r"\textcolor"
+        } else {
+            // Copy escapes whole so `\\class` isn't mistaken for markup
+            let len = rest[1..].chars().next().map_or(0, char::len_utf8);
+            out.push_str(&rest[..1 + len]);
+            rest = &rest[1 + len..];
+            continue;
+        };
+        let Some((arg, after_arg)) = group(&rest[command.len()..]) else {
+            out.push_str(command);
+            rest = &rest[command.len()..];
+            continue;
+        };
+        let Some((inner, after)) = group(after_arg.trim_start()) else {
+            out.push_str(command);
+            rest = &rest[command.len()..];
+            continue;
+        };
+        let highlighted =
+            command == r"\textcolor" || arg.split_whitespace().any(|class| class == "highlight");
+        let inner = highlight_markup(inner, highlight);
+        match highlight.filter(|_| highlighted) {
+            Some(c) => out.push_str(&format!(
+                r"\color{{#{:02x}{:02x}{:02x}}}{{{inner}}}",
+                c.r, c.g, c.b
+            )),
+            None => out.push_str(&format!("{{{inner}}}")),
+        }
+        rest = after;
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Splits a leading `{..}` group off `s`, returning its contents and what
+/// follows it.
+fn group(s: &str) -> Option<(&str, &str)> {
+    if !s.starts_with('{') {
+        return None;
+    }
+    let mut depth = 0;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((&s[1..i], &s[i + 1..]));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether XITS has a glyph for every character of `text`. Anything else
+/// renders as missing glyphs, so text in other scripts has to be shown some
+/// other way.
+pub fn is_covered(text: &str) -> anyhow::Result<bool> {
+    let font = load_font()?;
+    Ok(text.chars().all(|c| {
+        // Glyph 0 is `.notdef`, the missing glyph box
+        c.is_whitespace()
+            || matches!(font.gid_for_unicode_codepoint(c as u32), Some(gid) if gid.0 != 0)
+    }))
+}
+
+/// Turns the TeX spacing Symbolab uses between words in step titles back
+/// into plain spaces, so the text can be shown in any font.
+pub fn title_text(created_text: &str) -> String {
+    let spaced = created_text
+        .replace(r"\quad", " ")
+        .replace(r"\:", " ")
+        .replace(r"\;", " ")
+        .replace(r"\,", " ")
+        .replace(r"\ ", " ");
+    spaced.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_ascii_math() {
+        let cases = [
+            ("x^2+1", "x^2 + 1"),
+            ("x_{n}", "x_n"),
+            ("x^{n+1}", "x^(n + 1)"),
+            (r"\frac{1}{2}", "1/2"),
+            (r"\frac{x+1}{2}", "(x + 1)/2"),
+            (r"\sqrt{x+1}", "sqrt(x + 1)"),
+            (r"\bar{x}", "x"),
+            ("a<b", "a < b"),
+            (r"\class{highlight}{x}=3", "x = 3"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                ascii_math(&parse_tree(input).unwrap()),
+                expected,
+                "{input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn class_highlight_becomes_colour() {
+        let highlight = Colour::rgba(0xe5, 0x39, 0x35, 0xff);
+        let styled = styled(r"\class{highlight}{x}", Colour::BLACK, highlight).unwrap();
+        let ParseNode::Color(outer) = styled else {
+            panic!("expected the input colour, got {styled:?}");
+        };
+        match outer.inner.as_slice() {
+            [ParseNode::Color(inner)] => {
+                let RGBA(r, g, b, _) = inner.color;
+                assert_eq!((r, g, b), (highlight.r, highlight.g, highlight.b));
+            }
+            other => panic!("expected a highlighted node, got {other:?}"),
+        }
+    }
 }
@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{colour::Colour, config::RenderConfig};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Theme {
+    Light,
+    Dark,
+    HighContrast,
+    Sepia,
+}
+
+/// Colours for each part of a solution.
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    pub background: Colour,
+    /// Step inputs and the queries.
+    pub step_input: Colour,
+    pub result: Colour,
+    /// Terms Symbolab marks as changed in a step.
+    pub highlight: Colour,
+    /// Rule titles.
+    pub title: Colour,
+}
+
+impl Theme {
+    pub fn palette(self) -> Palette {
+        let (background, text, highlight, title) = match self {
+            Theme::Light => (0x00000000, 0x000000ff, 0x1565c0ff, 0x424242ff),
+            Theme::Dark => (0x121212ff, 0xe8e8e8ff, 0x64b5f6ff, 0xbdbdbdff),
+            Theme::HighContrast => (0x000000ff, 0xffffffff, 0xffff00ff, 0xffffffff),
+            Theme::Sepia => (0xf4ecd8ff, 0x5b4636ff, 0xb5651dff, 0x704214ff),
+        };
+        Palette {
+            background: rgba(background),
+            step_input: rgba(text),
+            result: rgba(text),
+            highlight: rgba(highlight),
+            title: rgba(title),
+        }
+    }
+}
+
+impl Palette {
+    /// The deployment's defaults, for requests without a theme.
+    pub fn from_config(config: &RenderConfig) -> Self {
+        Palette {
+            background: config.background,
+            step_input: config.foreground,
+            result: config.foreground,
+            highlight: config.highlight,
+            title: config.foreground,
+        }
+    }
+}
+
+/// Per-role overrides on top of the theme, as CSS colours.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RoleColours {
+    pub step_input: Option<String>,
+    pub result: Option<String>,
+    pub highlight: Option<String>,
+    pub title: Option<String>,
+}
+
+const fn rgba(rgba: u32) -> Colour {
+    Colour::rgba(
+        (rgba >> 24) as u8,
+        (rgba >> 16) as u8,
+        (rgba >> 8) as u8,
+        rgba as u8,
+    )
+}
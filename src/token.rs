@@ -0,0 +1,278 @@
+use anyhow::Context;
+use metrics::gauge;
+use rand::Rng;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    config::{env_duration, env_or},
+    error::{Error, Result},
+};
+
+const BACKOFF_BASE: Duration = Duration::from_millis(250);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+/// How long the pool may stay empty without a token arriving before the
+/// factory counts as stuck. Several full backoffs, so a burst that drains
+/// the pool or a brief upstream outage doesn't trip it.
+const STUCK_AFTER: Duration = Duration::from_secs(120);
+
+pub type TokenRequest = oneshot::Sender<String>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokenConfig {
+    /// Tokens kept fetched or in flight.
+    pub capacity: usize,
+    /// Requests that may queue for a token before senders wait.
+    pub buffer: usize,
+    /// Sent with every request to Symbolab.
+    pub user_agent: String,
+    #[serde(with = "humantime_serde")]
+    pub fetch_timeout: Duration,
+    /// How long a request waits for a token before giving up with a 503.
+    #[serde(with = "humantime_serde")]
+    pub wait_timeout: Duration,
+}
+
+impl Default for TokenConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10,
+            buffer: 20,
+            user_agent: "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/104.0.0.0 Safari/537.36".to_owned(),
+            fetch_timeout: Duration::from_secs(10),
+            wait_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl TokenConfig {
+    pub fn apply_env(&mut self) -> anyhow::Result<()> {
+        self.capacity = env_or("TOKEN_CAPACITY", self.capacity)?;
+        self.buffer = env_or("TOKEN_BUFFER", self.buffer)?;
+        self.user_agent = env_or("USER_AGENT", self.user_agent.clone())?;
+        self.fetch_timeout = env_duration(
+            "TOKEN_FETCH_TIMEOUT_SECS",
+            self.fetch_timeout,
+            Duration::from_secs,
+        )?;
+        self.wait_timeout = env_duration(
+            "TOKEN_WAIT_TIMEOUT_MS",
+            self.wait_timeout,
+            Duration::from_millis,
+        )?;
+        Ok(())
+    }
+}
+
+/// What the token factory reports about its pool, for readiness checks.
+#[derive(Debug, Default)]
+pub struct PoolStatus {
+    /// Fetched tokens waiting to be handed out.
+    pub ready: AtomicUsize,
+    /// Seconds since the epoch when the factory last started or fetched a
+    /// token.
+    progressed_at: AtomicU64,
+}
+
+impl PoolStatus {
+    fn progressed(&self) {
+        self.progressed_at.store(now_secs(), Ordering::Relaxed);
+    }
+
+    /// Whether the pool is empty and the factory hasn't fetched a token in
+    /// [`STUCK_AFTER`], so requests can't be served until something changes.
+    pub fn stuck(&self) -> bool {
+        let idle = now_secs().saturating_sub(self.progressed_at.load(Ordering::Relaxed));
+        self.ready.load(Ordering::Relaxed) == 0 && idle > STUCK_AFTER.as_secs()
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Keeps a pool of tokens topped up and hands them out to requesters,
+/// reporting on it through `pool`. Returns `Ok` once every sender of `rx`
+/// has been dropped.
+#[instrument(skip_all)]
+pub async fn token_factory(
+    rx: &mut mpsc::Receiver<TokenRequest>,
+    pool: Arc<PoolStatus>,
+    config: &TokenConfig,
+) -> anyhow::Result<()> {
+    let capacity = config.capacity;
+    let queue_len = Arc::new(AtomicUsize::new(0));
+    pool.ready.store(0, Ordering::Relaxed);
+    pool.progressed();
+
+    let client = Client::builder()
+        .timeout(config.fetch_timeout)
+        .user_agent(&config.user_agent)
+        .build()?;
+    let (tx_token, mut rx_token) = mpsc::channel(capacity);
+    let (tx_internal, mut rx_internal) = mpsc::channel(capacity);
+
+    {
+        let queue_len = queue_len.clone();
+        let pool = pool.clone();
+        let tx_token = tx_token.clone();
+        tokio::spawn(async move {
+            while let Some(()) = rx_internal.recv().await {
+                queue_len.fetch_add(1, Ordering::Relaxed);
+                gauge!(
+                    "symbolab_token_pool_depth",
+                    queue_len.load(Ordering::Relaxed) as f64
+                );
+                {
+                    let tx_token = tx_token.clone();
+                    let client = client.clone();
+                    let pool = pool.clone();
+                    tokio::spawn(async move {
+                        let token = get_token_with_retry(&client).await;
+                        pool.ready.fetch_add(1, Ordering::Relaxed);
+                        pool.progressed();
+                        if tx_token.send(token).await.is_err() {
+                            warn!("factory stopped before token was delivered");
+                        }
+                    });
+                }
+            }
+        });
+    }
+
+    info!("starting");
+    for _ in 0..capacity {
+        tx_internal.send(()).await?;
+    }
+    info!("queued {capacity} tokens");
+
+    while let Some(channel) = rx.recv().await {
+        if channel.is_closed() {
+            // The requester already timed out, don't spend a token on it
+            continue;
+        }
+        queue_len.fetch_sub(1, Ordering::Relaxed);
+        gauge!(
+            "symbolab_token_pool_depth",
+            queue_len.load(Ordering::Relaxed) as f64
+        );
+        if queue_len.load(Ordering::Relaxed) == 0 {
+            warn!("ran out of tokens!");
+        }
+        if let Some(token) = rx_token.recv().await {
+            pool.ready.fetch_sub(1, Ordering::Relaxed);
+            match channel.send(token) {
+                Ok(_) => {
+                    tx_internal.send(()).await?;
+                }
+                Err(token) => {
+                    // The requester gave up while we were waiting, keep the token
+                    queue_len.fetch_add(1, Ordering::Relaxed);
+                    gauge!(
+                        "symbolab_token_pool_depth",
+                        queue_len.load(Ordering::Relaxed) as f64
+                    );
+                    pool.ready.fetch_add(1, Ordering::Relaxed);
+                    tx_token.send(token).await?;
+                }
+            }
+        } else {
+            return Err(anyhow::anyhow!("all channels closed"));
+        }
+    }
+
+    info!("every requester is gone, stopping");
+    Ok(())
+}
+
+async fn get_token_with_retry(client: &Client) -> String {
+    let mut delay = BACKOFF_BASE;
+    let mut attempt = 1u32;
+    loop {
+        match get_token(client).await {
+            Ok(token) => return token,
+            Err(e) => {
+                let jitter = rand::thread_rng().gen_range(0..=delay.as_millis() as u64);
+                let wait = delay + Duration::from_millis(jitter);
+                error!(
+                    attempt,
+                    "failed to fetch token, retrying in {wait:?}: {e:#}"
+                );
+                tokio::time::sleep(wait).await;
+                delay = (delay * 2).min(BACKOFF_MAX);
+                attempt = attempt.saturating_add(1);
+            }
+        }
+    }
+}
+
+pub async fn get_token(client: &Client) -> anyhow::Result<String> {
+    let res = client
+        .get("https://www.symbolab.com/solver/step-by-step/")
+        .send()
+        .await;
+    let res = crate::metrics::upstream_response("token", res)?;
+
+    let set_cookie = res
+        .headers()
+        .get_all("set-cookie")
+        .iter()
+        .map(|h| h.to_str())
+        .collect::<core::result::Result<Vec<_>, _>>()?;
+
+    let token = set_cookie
+        .iter()
+        .find_map(|s| {
+            s.split("; ")
+                .find_map(|pair| {
+                    pair.split(", ")
+                        .map(|p2| p2.split('='))
+                        .map(|mut arr| (arr.next(), arr))
+                        .find(|(first, _)| *first == Some("sy2.pub.token"))
+                })?
+                .1
+                .next()
+        })
+        .context("No token")?;
+
+    Ok(token.to_owned())
+}
+
+pub async fn get_cached_token(
+    channel: &mpsc::Sender<TokenRequest>,
+    timeout: Duration,
+) -> Result<String> {
+    let wait = async {
+        let (tx, rx) = oneshot::channel();
+        channel.send(tx).await.ok()?;
+        rx.await.ok()
+    };
+    match tokio::time::timeout(timeout, wait).await {
+        Ok(Some(token)) => Ok(token),
+        Ok(None) => {
+            warn!("token factory went away while waiting for a token");
+            Err(Error::Unavailable {
+                retry_after: timeout,
+            })
+        }
+        Err(_) => {
+            warn!("timed out waiting for a token");
+            Err(Error::Unavailable {
+                retry_after: timeout,
+            })
+        }
+    }
+}
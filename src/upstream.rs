@@ -0,0 +1,363 @@
+use std::{
+    collections::HashMap,
+    env,
+    future::Future,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use axum::http::HeaderMap;
+use metrics::histogram;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+use crate::{
+    config::{env_duration, env_or},
+    error::{Error, Result},
+};
+
+/// Buckets for clients that have been idle long enough to be full again are
+/// dropped once the map grows past this size.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+/// Longest a batch item waits for its client's rate limit before failing,
+/// so overlapping batches can't queue without bound.
+pub const MAX_BATCH_WAIT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamConfig {
+    /// Upstream requests per second across all clients.
+    pub global_rate: f64,
+    pub global_burst: f64,
+    /// Upstream requests per second for a single client.
+    pub client_rate: f64,
+    pub client_burst: f64,
+    /// Longest a request may queue for the global limit before getting a 503.
+    #[serde(with = "humantime_serde")]
+    pub max_queue_wait: Duration,
+    pub max_concurrency: usize,
+    /// Consecutive failures before the circuit opens.
+    pub breaker_threshold: u32,
+    #[serde(with = "humantime_serde")]
+    pub breaker_cooldown: Duration,
+    /// Header the proxy in front sets to the client's address, like
+    /// `fly-client-ip`. Clients can set any header themselves, so this is
+    /// only trusted when configured, and otherwise the peer address is used.
+    pub client_ip_header: Option<String>,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            global_rate: 20.0,
+            global_burst: 40.0,
+            client_rate: 2.0,
+            client_burst: 10.0,
+            max_queue_wait: Duration::from_millis(2000),
+            max_concurrency: 32,
+            breaker_threshold: 5,
+            breaker_cooldown: Duration::from_secs(30),
+            client_ip_header: None,
+        }
+    }
+}
+
+impl UpstreamConfig {
+    pub fn apply_env(&mut self) -> anyhow::Result<()> {
+        self.global_rate = env_or("UPSTREAM_RATE", self.global_rate)?;
+        self.global_burst = env_or("UPSTREAM_BURST", self.global_burst)?;
+        self.client_rate = env_or("UPSTREAM_CLIENT_RATE", self.client_rate)?;
+        self.client_burst = env_or("UPSTREAM_CLIENT_BURST", self.client_burst)?;
+        self.max_queue_wait = env_duration(
+            "UPSTREAM_MAX_QUEUE_WAIT_MS",
+            self.max_queue_wait,
+            Duration::from_millis,
+        )?;
+        self.max_concurrency = env_or("UPSTREAM_CONCURRENCY", self.max_concurrency)?;
+        self.breaker_threshold = env_or("BREAKER_THRESHOLD", self.breaker_threshold)?;
+        self.breaker_cooldown = env_duration(
+            "BREAKER_COOLDOWN_SECS",
+            self.breaker_cooldown,
+            Duration::from_secs,
+        )?;
+        if let Ok(header) = env::var("CLIENT_IP_HEADER") {
+            self.client_ip_header = Some(header).filter(|header| !header.is_empty());
+        }
+        Ok(())
+    }
+}
+
+/// Token bucket, also used for per-key limits in `keys`.
+#[derive(Debug)]
+pub struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    pub fn new(burst: f64) -> Self {
+        Self {
+            tokens: burst,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, rate: f64, burst: f64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.updated = now;
+    }
+
+    /// Takes a token if it becomes valid within `max_wait`, returning how
+    /// long the caller has to wait for it. The debt is recorded so later
+    /// callers queue behind it. Otherwise nothing is taken and the error is
+    /// how long the wait would have been.
+    pub fn take(
+        &mut self,
+        rate: f64,
+        burst: f64,
+        max_wait: Duration,
+    ) -> core::result::Result<Duration, Duration> {
+        self.refill(rate, burst);
+        let wait = Duration::from_secs_f64((1.0 - self.tokens).max(0.0) / rate);
+        if wait > max_wait {
+            return Err(wait);
+        }
+        self.tokens -= 1.0;
+        Ok(wait)
+    }
+
+    /// Takes a token only if one is available now, otherwise returns how long
+    /// until one will be.
+    pub fn try_take(&mut self, rate: f64, burst: f64) -> core::result::Result<(), Duration> {
+        self.refill(rate, burst);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+}
+
+/// Who an upstream request is made for, which decides how the per-client
+/// limit applies.
+#[derive(Debug, Clone, Copy)]
+pub enum Caller<'a> {
+    /// Background work like revalidation and warm-ups, only under the global
+    /// limit.
+    Internal,
+    /// A request a client is waiting on, refused once it's over its limit.
+    Client(&'a str),
+    /// An item of a client's batch. Waits for the client's next token instead
+    /// of failing, so large batches are paced rather than rejected.
+    Batch(&'a str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { since: Instant },
+}
+
+impl BreakerState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BreakerState::Closed { .. } => "closed",
+            BreakerState::Open { .. } => "open",
+            BreakerState::HalfOpen { .. } => "half_open",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Upstream {
+    config: UpstreamConfig,
+    concurrency: Semaphore,
+    global: Mutex<Bucket>,
+    clients: Mutex<HashMap<String, Bucket>>,
+    breaker: Mutex<BreakerState>,
+}
+
+impl Upstream {
+    pub fn new(config: UpstreamConfig) -> Self {
+        Self {
+            concurrency: Semaphore::new(config.max_concurrency),
+            global: Mutex::new(Bucket::new(config.global_burst)),
+            clients: Mutex::new(HashMap::new()),
+            breaker: Mutex::new(BreakerState::Closed { failures: 0 }),
+            config,
+        }
+    }
+
+    pub fn breaker_state(&self) -> BreakerState {
+        *self.breaker.lock().unwrap()
+    }
+
+    /// Runs `f` against upstream, subject to the circuit breaker, the
+    /// per-client and global rate limits and the concurrency limit.
+    pub async fn call<T, F, Fut>(&self, caller: Caller<'_>, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        self.check_breaker()?;
+        match caller {
+            Caller::Internal => {}
+            Caller::Client(client) => self.check_client(client)?,
+            Caller::Batch(client) => self.wait_client(client).await?,
+        }
+        self.wait_global().await?;
+        let _permit = self
+            .concurrency
+            .acquire()
+            .await
+            .context("upstream semaphore closed")?;
+
+        let start = Instant::now();
+        let res = f().await;
+        let outcome = if res.is_ok() { "ok" } else { "error" };
+        histogram!("symbolab_upstream_duration_seconds", start.elapsed(), "outcome" => outcome);
+        self.record(res.is_ok());
+        res
+    }
+
+    fn check_breaker(&self) -> Result<()> {
+        let mut breaker = self.breaker.lock().unwrap();
+        let now = Instant::now();
+        match *breaker {
+            BreakerState::Closed { .. } => Ok(()),
+            BreakerState::Open { until } if now >= until => {
+                info!("circuit half-open, sending trial request");
+                *breaker = BreakerState::HalfOpen { since: now };
+                Ok(())
+            }
+            BreakerState::Open { until } => Err(Error::Unavailable {
+                retry_after: until - now,
+            }),
+            // The trial request never reported back, allow another one
+            BreakerState::HalfOpen { since } if now - since >= self.config.breaker_cooldown => {
+                *breaker = BreakerState::HalfOpen { since: now };
+                Ok(())
+            }
+            BreakerState::HalfOpen { .. } => Err(Error::Unavailable {
+                retry_after: self.config.breaker_cooldown,
+            }),
+        }
+    }
+
+    fn record(&self, ok: bool) {
+        let mut breaker = self.breaker.lock().unwrap();
+        let next = match (*breaker, ok) {
+            (_, true) => BreakerState::Closed { failures: 0 },
+            (BreakerState::Closed { failures }, false)
+                if failures + 1 < self.config.breaker_threshold =>
+            {
+                BreakerState::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (BreakerState::Open { until }, false) => BreakerState::Open { until },
+            (_, false) => BreakerState::Open {
+                until: Instant::now() + self.config.breaker_cooldown,
+            },
+        };
+        if next.name() != breaker.name() {
+            warn!("circuit {} -> {}", breaker.name(), next.name());
+        }
+        *breaker = next;
+    }
+
+    fn check_client(&self, client: &str) -> Result<()> {
+        let UpstreamConfig {
+            client_rate,
+            client_burst,
+            ..
+        } = self.config;
+        self.with_client(client, |bucket| bucket.try_take(client_rate, client_burst))
+            .map_err(|retry_after| {
+                warn!(client, "client rate limited");
+                Error::RateLimited { retry_after }
+            })
+    }
+
+    /// Takes the client's next token and waits for it, unless that's more
+    /// than [`MAX_BATCH_WAIT`] off.
+    async fn wait_client(&self, client: &str) -> Result<()> {
+        let UpstreamConfig {
+            client_rate,
+            client_burst,
+            ..
+        } = self.config;
+        let wait = self
+            .with_client(client, |bucket| {
+                bucket.take(client_rate, client_burst, MAX_BATCH_WAIT)
+            })
+            .map_err(|retry_after| {
+                warn!(client, "client rate limit saturated");
+                Error::Unavailable { retry_after }
+            })?;
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+
+    fn with_client<T>(&self, client: &str, f: impl FnOnce(&mut Bucket) -> T) -> T {
+        let UpstreamConfig {
+            client_rate,
+            client_burst,
+            ..
+        } = self.config;
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_TRACKED_CLIENTS && !clients.contains_key(client) {
+            clients.retain(|_, bucket| {
+                bucket.refill(client_rate, client_burst);
+                bucket.tokens < client_burst
+            });
+        }
+        f(clients
+            .entry(client.to_owned())
+            .or_insert_with(|| Bucket::new(client_burst)))
+    }
+
+    async fn wait_global(&self) -> Result<()> {
+        let wait = self
+            .global
+            .lock()
+            .unwrap()
+            .take(
+                self.config.global_rate,
+                self.config.global_burst,
+                self.config.max_queue_wait,
+            )
+            .map_err(|retry_after| {
+                warn!("upstream rate limit saturated");
+                Error::Unavailable { retry_after }
+            })?;
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+}
+
+/// Identifies the caller for per-client limits, preferring the address the
+/// trusted proxy saw over the proxy's own. For `X-Forwarded-For` that's the
+/// last hop, the one the proxy appended; earlier ones came from the client.
+pub fn client_key(headers: &HeaderMap, addr: SocketAddr, config: &UpstreamConfig) -> String {
+    config
+        .client_ip_header
+        .as_deref()
+        .and_then(|name| headers.get(name))
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.rsplit(',').next())
+        .map(|h| h.trim().to_owned())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| addr.ip().to_string())
+}
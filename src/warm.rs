@@ -0,0 +1,164 @@
+use std::{
+    fs,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use anyhow::{ensure, Context};
+use futures::{stream, StreamExt};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::{
+    config::Config,
+    solve,
+    symbolab::Language,
+    upstream::{Bucket, Caller},
+    Payload, State,
+};
+
+#[derive(Debug, Default, Serialize)]
+pub struct WarmReport {
+    pub warmed: usize,
+    pub failed: Vec<WarmFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WarmFailure {
+    pub query: String,
+    pub status: u16,
+    pub message: String,
+}
+
+/// Solves each payload that isn't already cached and stores the result.
+/// Runs outside any client's rate limit but still under the global one, and
+/// paces itself to `warm_rate` so live traffic isn't starved.
+pub async fn warm(state: &State, payloads: Vec<Payload>) -> WarmReport {
+    let total = payloads.len();
+    let rate = state.config.warm_rate;
+    let pacer = Mutex::new(Bucket::new(1.0));
+    let done = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+    // Roughly every 5%, so big corpora don't flood the log
+    let every = (total / 20).max(1);
+    info!(total, "warming cache");
+
+    let results = stream::iter(payloads)
+        .map(|payload| {
+            let (pacer, done, failed) = (&pacer, &done, &failed);
+            async move {
+                let res = async {
+                    let payload = match payload.clone().normalize() {
+                        Ok(payload) => payload,
+                        Err(e) => return Err((payload, e)),
+                    };
+                    let cached = state.response_cache.get_raw(&payload).await;
+                    if matches!(cached, Some(data) if !data.unrendered) {
+                        return Ok(());
+                    }
+                    // Only warm-up waits on the pacer, so the debt stays
+                    // bounded by `warm_concurrency`
+                    let wait = pacer
+                        .lock()
+                        .await
+                        .take(rate, 1.0, Duration::MAX)
+                        .unwrap_or_default();
+                    tokio::time::sleep(wait).await;
+                    match solve(state, Caller::Internal, &payload).await {
+                        Ok(data) => {
+                            state.response_cache.insert(payload, data).await;
+                            Ok(())
+                        }
+                        Err(e) => Err((payload, e)),
+                    }
+                }
+                .await;
+                if let Err((payload, e)) = &res {
+                    failed.fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        query = payload.query,
+                        "failed to warm cache entry: {}",
+                        e.message()
+                    );
+                }
+                let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                if done % every == 0 && done < total {
+                    info!(
+                        done,
+                        total,
+                        failed = failed.load(Ordering::Relaxed),
+                        "warming cache"
+                    );
+                }
+                res
+            }
+        })
+        .buffer_unordered(state.config.warm_concurrency)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut report = WarmReport::default();
+    for result in results {
+        match result {
+            Ok(()) => report.warmed += 1,
+            Err((payload, e)) => report.failed.push(WarmFailure {
+                query: payload.query,
+                status: e.status().as_u16(),
+                message: e.message(),
+            }),
+        }
+    }
+    info!(
+        warmed = report.warmed,
+        failed = report.failed.len(),
+        "cache warm-up finished"
+    );
+    report
+}
+
+/// Reads a corpus with one query per line. Lines starting with `{` are
+/// parsed as a full request body, to warm other languages or colours; blank
+/// lines and lines starting with `#` are skipped.
+pub fn read_corpus(path: &Path) -> anyhow::Result<Vec<Payload>> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            if line.starts_with('{') {
+                serde_json::from_str(line)
+                    .with_context(|| format!("{}:{number}: invalid payload", path.display()))
+            } else {
+                Ok(Payload {
+                    query: line.to_owned(),
+                    foreground: None,
+                    background: None,
+                    theme: None,
+                    colours: Default::default(),
+                    language: Language::default(),
+                    options: Default::default(),
+                    images: Default::default(),
+                })
+            }
+        })
+        .collect()
+}
+
+/// Warms the cache snapshot without serving, so a fresh deployment starts
+/// with it already filled.
+pub async fn offline(config: Config, corpus: &Path) -> anyhow::Result<WarmReport> {
+    ensure!(
+        config.cache.snapshot_path.is_some(),
+        "cache.snapshot_path must be set to warm the cache offline"
+    );
+    let payloads = read_corpus(corpus)?;
+    let (state, _factory) = State::new(config).await?;
+    let report = warm(&state, payloads).await;
+    let saved = state.response_cache.save_snapshot().await?;
+    info!("saved {saved} cache entries to snapshot");
+    Ok(report)
+}
@@ -0,0 +1,216 @@
+//! Renders `tests/golden/corpus.toml` and compares it against the checked-in
+//! images, so ReX, font or pathfinder upgrades that change rendering are
+//! caught before deploy.
+//!
+//! SVGs that differ textually are rasterised and compared by pixel, so
+//! float formatting changes alone don't fail. Set `UPDATE_GOLDEN=1` to
+//! rewrite the expected files instead of comparing; failing cases leave
+//! their actual and diff images in `target/tmp/golden`.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, ensure, Context};
+use serde::Deserialize;
+use symbolab_rs::{
+    clean_latex, config::Config, get_image_set_sync, rasterise, tex, theme::Palette, RenderContext,
+};
+use tiny_skia::Pixmap;
+
+/// How far a channel may drift before its pixel counts as changed, which
+/// absorbs anti-aliasing differences between rasteriser versions.
+const CHANNEL_TOLERANCE: u8 = 24;
+/// Fraction of pixels that may change before a case fails.
+const MAX_CHANGED: f64 = 0.002;
+
+#[derive(Debug, Deserialize)]
+struct Corpus {
+    case: Vec<Case>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Case {
+    name: String,
+    latex: String,
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn failure_dir() -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden")
+}
+
+fn updating() -> bool {
+    matches!(env::var("UPDATE_GOLDEN").as_deref(), Ok(value) if !value.is_empty() && value != "0")
+}
+
+fn corpus() -> Vec<Case> {
+    let path = golden_dir().join("corpus.toml");
+    let text = fs::read_to_string(&path).expect("failed to read corpus");
+    toml::from_str::<Corpus>(&text)
+        .expect("failed to parse corpus")
+        .case
+}
+
+fn config() -> Config {
+    let mut config = Config::default();
+    // The default leaves most of each image empty, diluting the diff
+    config.render.padding = 8;
+    config
+}
+
+/// Runs `check` on every case and reports all failures together.
+fn run(kind: &str, mut check: impl FnMut(&Case) -> anyhow::Result<()>) {
+    let failures = corpus()
+        .iter()
+        .filter_map(|case| check(case).err().map(|e| format!("{}: {:#}", case.name, e)))
+        .collect::<Vec<_>>();
+    if !failures.is_empty() {
+        panic!(
+            "{} of the {kind} goldens failed, see {} and rerun with UPDATE_GOLDEN=1 if the changes are intended:\n{}",
+            failures.len(),
+            failure_dir().display(),
+            failures.join("\n")
+        );
+    }
+}
+
+#[test]
+fn svg() {
+    let config = config();
+    let palette = Palette::from_config(&config.render);
+    run("SVG", |case| {
+        let latex = clean_latex(&case.latex);
+        let actual = tex::get_svg(
+            &latex,
+            palette.step_input,
+            palette.highlight,
+            config.render.layout_width,
+        )?;
+        let path = golden_dir().join("svg").join(format!("{}.svg", case.name));
+        if updating() {
+            return write(&path, actual.as_bytes());
+        }
+        let expected =
+            fs::read_to_string(&path).with_context(|| format!("missing {}", path.display()))?;
+        if actual == expected {
+            return Ok(());
+        }
+        let expected = rasterise(&expected, palette.background, &config.render)?;
+        let rendered = rasterise(&actual, palette.background, &config.render)?;
+        let res = compare(&format!("{}.svg", case.name), &expected, &rendered);
+        if res.is_err() {
+            let path = failure_dir().join(format!("{}.svg.actual.svg", case.name));
+            write(&path, actual.as_bytes())?;
+        }
+        res
+    });
+}
+
+#[test]
+fn image_set() {
+    let config = config();
+    let palette = Palette::from_config(&config.render);
+    let ctx = RenderContext::inline(palette, Arc::new(config));
+    run("PNG", |case| {
+        let latex = clean_latex(&case.latex);
+        let images = get_image_set_sync(&latex, palette.step_input, &ctx)?;
+        ensure!(images.mathml.is_some(), "no MathML");
+        ensure!(images.spoken.is_some(), "no spoken text");
+        ensure!(images.text.is_some(), "no AsciiMath");
+        let webp = images.webp.context("no WebP")?;
+        let actual = decode_webp(&webp)?;
+        let path = golden_dir().join("png").join(format!("{}.png", case.name));
+        if updating() {
+            return write(&path, &actual.encode_png()?);
+        }
+        let expected =
+            Pixmap::load_png(&path).with_context(|| format!("missing {}", path.display()))?;
+        compare(&format!("{}.webp", case.name), &expected, &actual)
+    });
+}
+
+/// Pixels of an inline `data:image/webp` URI.
+fn decode_webp(uri: &str) -> anyhow::Result<Pixmap> {
+    let encoded = uri
+        .strip_prefix("data:image/webp;base64,")
+        .context("WebP isn't inline")?;
+    let bytes = base64::decode(encoded)?;
+    let image = webp::Decoder::new(&bytes)
+        .decode()
+        .context("failed to decode WebP")?;
+    ensure!(image.is_alpha(), "WebP has no alpha channel");
+    let mut pixmap = Pixmap::new(image.width(), image.height()).context("empty WebP")?;
+    // Encoded straight from a pixmap, so already premultiplied
+    pixmap.data_mut().copy_from_slice(&image);
+    Ok(pixmap)
+}
+
+/// Fails when the sizes differ or more than `MAX_CHANGED` of the pixels
+/// drift past `CHANNEL_TOLERANCE`, saving the actual image and a diff with
+/// changed pixels in red.
+fn compare(name: &str, expected: &Pixmap, actual: &Pixmap) -> anyhow::Result<()> {
+    let dir = failure_dir();
+    if (expected.width(), expected.height()) != (actual.width(), actual.height()) {
+        write(
+            &dir.join(format!("{name}.actual.png")),
+            &actual.encode_png()?,
+        )?;
+        bail!(
+            "size changed from {}x{} to {}x{}",
+            expected.width(),
+            expected.height(),
+            actual.width(),
+            actual.height()
+        );
+    }
+
+    let mut diff = actual.clone();
+    let mut changed = 0;
+    for ((old, new), out) in expected
+        .data()
+        .chunks_exact(4)
+        .zip(actual.data().chunks_exact(4))
+        .zip(diff.data_mut().chunks_exact_mut(4))
+    {
+        if old
+            .iter()
+            .zip(new)
+            .any(|(a, b)| a.abs_diff(*b) > CHANNEL_TOLERANCE)
+        {
+            changed += 1;
+            out.copy_from_slice(&[0xff, 0, 0, 0xff]);
+        } else {
+            // Faded, so the changes stand out
+            out[3] /= 4;
+            out[..3].iter_mut().for_each(|c| *c /= 4);
+        }
+    }
+    let total = (actual.width() * actual.height()) as f64;
+    let fraction = changed as f64 / total;
+    if fraction > MAX_CHANGED {
+        write(
+            &dir.join(format!("{name}.actual.png")),
+            &actual.encode_png()?,
+        )?;
+        write(&dir.join(format!("{name}.diff.png")), &diff.encode_png()?)?;
+        bail!(
+            "{changed} pixels ({:.2}%) changed, at most {:.2}% may",
+            fraction * 100.0,
+            MAX_CHANGED * 100.0
+        );
+    }
+    Ok(())
+}
+
+fn write(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, bytes).with_context(|| format!("failed to write {}", path.display()))
+}